@@ -0,0 +1,48 @@
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+use k8s_openapi::jiff::Timestamp;
+
+pub const READY: &str = "Ready";
+pub const DEGRADED: &str = "Degraded";
+
+/// `Ready=True` for a resource whose spec at `observed_generation` has been
+/// fully reconciled
+pub fn ready(observed_generation: Option<i64>, message: &str) -> Condition {
+    condition(READY, true, "Reconciled", message, observed_generation)
+}
+
+/// `Degraded=True` with a CamelCase `reason`, e.g. `LoadBalancerPending`
+pub fn degraded(reason: &str, message: &str) -> Condition {
+    condition(DEGRADED, true, reason, message, None)
+}
+
+pub fn condition(
+    type_: &str,
+    status: bool,
+    reason: &str,
+    message: &str,
+    observed_generation: Option<i64>,
+) -> Condition {
+    Condition {
+        type_: type_.to_owned(),
+        status: if status { "True" } else { "False" }.to_owned(),
+        reason: reason.to_owned(),
+        message: message.to_owned(),
+        observed_generation,
+        last_transition_time: Time(Timestamp::now()),
+    }
+}
+
+/// Adds `condition`, replacing any existing one of the same type. When the
+/// status hasn't changed the existing `lastTransitionTime` is kept, so it
+/// records when the status last flipped rather than the last reconcile.
+pub fn set(conditions: &mut Vec<Condition>, mut condition: Condition) {
+    match conditions.iter_mut().find(|c| c.type_ == condition.type_) {
+        Some(existing) => {
+            if existing.status == condition.status {
+                condition.last_transition_time = existing.last_transition_time.clone();
+            }
+            *existing = condition;
+        }
+        None => conditions.push(condition),
+    }
+}
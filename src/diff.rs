@@ -0,0 +1,96 @@
+use kube::Resource;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Whether applying `desired` would change `live`; see `changed_fields`
+pub fn needs_update<K: Resource + Serialize>(desired: &K, live: &K) -> bool {
+    !changed_fields(desired, live).is_empty()
+}
+
+/// Paths of the managed fields where `desired` and `live` differ, e.g.
+/// `metadata.labels.app` or `spec.ports`, for logging why an apply happens.
+///
+/// Only the labels are compared from `metadata`, so server-populated fields
+/// such as `resourceVersion`, `uid`, `managedFields` and `creationTimestamp`
+/// never count as changes, and neither do labels added by other controllers.
+/// `status` is ignored. `spec` is compared field by field, skipping fields
+/// `desired` leaves unset because the API server fills in defaults there;
+/// a field dropped from a spec is therefore not detected. Other top-level
+/// fields, such as ConfigMap `data`, must match exactly. An object that fails
+/// to serialize is reported as changed at the root (`""`).
+pub fn changed_fields<K: Resource + Serialize>(desired: &K, live: &K) -> Vec<String> {
+    let (Ok(Value::Object(desired)), Ok(Value::Object(live))) =
+        (serde_json::to_value(desired), serde_json::to_value(live))
+    else {
+        return vec![String::new()];
+    };
+
+    let mut changed = Vec::new();
+    let labels = |object: &serde_json::Map<String, Value>| {
+        object
+            .get("metadata")
+            .and_then(|metadata| metadata.get("labels"))
+            .cloned()
+            .unwrap_or(Value::Null)
+    };
+    contained(
+        &labels(&desired),
+        &labels(&live),
+        "metadata.labels",
+        &mut changed,
+    );
+
+    let keys = desired
+        .keys()
+        .chain(live.keys().filter(|key| !desired.contains_key(*key)));
+    for key in keys {
+        if matches!(key.as_str(), "apiVersion" | "kind" | "metadata" | "status") {
+            continue;
+        }
+        let desired = desired.get(key).unwrap_or(&Value::Null);
+        let live = live.get(key).unwrap_or(&Value::Null);
+        if key == "spec" {
+            contained(desired, live, key, &mut changed);
+        } else if !(is_unset(desired) && is_unset(live)) && desired != live {
+            changed.push(key.clone());
+        }
+    }
+    changed
+}
+
+/// Records `path` where `desired` isn't set identically in `live`, ignoring
+/// whatever `live` has beyond it
+fn contained(desired: &Value, live: &Value, path: &str, changed: &mut Vec<String>) {
+    match (desired, live) {
+        (desired, _) if is_unset(desired) => {}
+        (Value::Object(desired), Value::Object(live)) => {
+            for (key, value) in desired {
+                let live = live.get(key).unwrap_or(&Value::Null);
+                contained(value, live, &format!("{path}.{key}"), changed);
+            }
+        }
+        (Value::Array(desired), Value::Array(live)) => {
+            let same = desired.len() == live.len()
+                && desired.iter().zip(live).all(|(desired, live)| {
+                    let mut nested = Vec::new();
+                    contained(desired, live, path, &mut nested);
+                    nested.is_empty()
+                });
+            if !same {
+                changed.push(path.to_owned());
+            }
+        }
+        (desired, live) if desired != live => changed.push(path.to_owned()),
+        _ => {}
+    }
+}
+
+/// `null`, `{}` and `[]` all serialize from an unset optional field
+fn is_unset(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::Object(object) => object.is_empty(),
+        Value::Array(array) => array.is_empty(),
+        _ => false,
+    }
+}
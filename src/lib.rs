@@ -1,10 +1,30 @@
+use either::Either;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use kube::Resource;
+use kube::api::{Api, PatchParams, PostParams};
+use kube::core::Status;
+use kube_runtime::controller::Action;
+use kube_runtime::finalizer::{Event as FinalizerEvent, finalizer};
+use kube_runtime::wait::{await_condition, conditions::is_deleted};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
 use tokio::task::JoinError;
 use tokio::time::error::Elapsed;
 use tracing::instrument;
 
 pub mod basic_auth;
+pub mod conditions;
+pub mod diff;
+pub mod multiaddr;
+pub mod resources;
+pub mod retry;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
 pub mod types;
 
 #[derive(Error, Debug)]
@@ -62,53 +82,470 @@ pub enum Error {
         source: rand::Error,
     },
 
-    #[error("Node inputs are not defined")]
-    MissingNodeInputs(String),
+    /// Lists the names of the missing reconcile inputs, so callers can
+    /// report them on the resource status
+    #[error("Node inputs are not defined: {}", .0.join(", "))]
+    MissingNodeInputs(Vec<String>),
 
     #[error("Error decoding key")]
     DecodeKeyError(String),
 
-    #[error("Error fetching configmap")]
-    ConfigMapError(String),
+    #[error("ConfigMap {namespace}/{name} not found")]
+    ConfigMapNotFound { name: String, namespace: String },
+
+    #[error("ConfigMap {name} has no data")]
+    ConfigMapMissingData { name: String },
+
+    #[error("ConfigMap {name} is missing key {key}")]
+    ConfigMapMissingKey { name: String, key: String },
 
     #[error("Error fetching secret")]
     SecretMapError(String),
 
+    #[error("Error fetching statefulset")]
+    StatefulSetError(String),
+
+    #[error("Error fetching service")]
+    ServiceError(String),
+
     #[error("External address missing")]
     ExternalAddressMissing(String),
+
+    #[error("NodePort {0} is outside the 30000-32767 range")]
+    InvalidNodePort(i32),
+
+    #[error("Invalid CIDR: {0}")]
+    InvalidCidr(String),
+
+    #[error("Invalid label value: {0}")]
+    InvalidLabel(String),
+
+    #[error("Duplicate port name: {0}")]
+    DuplicatePortName(String),
+
+    #[error("Port name is required when a Service has more than one port")]
+    MissingPortName,
+
+    #[error("Operation cancelled")]
+    Cancelled,
+
+    #[error("{0} is only valid for LoadBalancer Services")]
+    LoadBalancerOnly(String),
+
+    #[error("healthCheckNodePort requires externalTrafficPolicy: Local")]
+    HealthCheckNodePortRequiresLocal,
+
+    #[error("Invalid multiaddr: {0}")]
+    InvalidMultiaddr(String),
+
+    #[error("HPA minReplicas {0} is greater than maxReplicas {1}")]
+    InvalidHpaRange(i32, i32),
+
+    #[error("Container not found: {0}")]
+    ContainerNotFound(String),
+
+    #[error("Invalid quantity: {0}")]
+    InvalidQuantity(String),
+
+    #[error("Target port {0} is not a container port")]
+    UnknownTargetPort(String),
+
+    #[error("LoadBalancer name template must contain {{idx}}: {0}")]
+    InvalidNameTemplate(String),
+
+    #[error("roleRef kind must be Role or ClusterRole, got {0}")]
+    InvalidRoleRefKind(String),
+
+    #[error("LoadBalancer failed to provision: {0}")]
+    LoadBalancerFailed(String),
+
+    #[error("sessionAffinityConfig requires sessionAffinity: ClientIP")]
+    ClientIpAffinityOnly,
+
+    #[error("NetworkPolicy must restrict ingress, egress or both")]
+    UnrestrictedNetworkPolicy,
+
+    #[error("Replica count must not be negative: {0}")]
+    NegativeReplicas(i32),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Field manager used for server-side apply unless the caller picks another
+pub const DEFAULT_FIELD_MANAGER: &str = "ipfs-operator";
+
+/// Server-side apply settings shared by the `deploy` functions
+#[derive(Debug, Clone)]
+pub struct ApplyOptions {
+    /// Identifies the controller that owns the applied fields. Keep this the
+    /// same across every object an operator manages so that conflicts between
+    /// controllers are detected.
+    pub field_manager: String,
+    /// Server-side dry run: the API server validates and returns the result
+    /// without persisting it
+    pub dry_run: bool,
+}
+
+impl Default for ApplyOptions {
+    fn default() -> Self {
+        Self::new(DEFAULT_FIELD_MANAGER)
+    }
+}
+
+impl ApplyOptions {
+    pub fn new(field_manager: &str) -> Self {
+        Self {
+            field_manager: field_manager.to_owned(),
+            dry_run: false,
+        }
+    }
+
+    pub fn dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+
+    pub fn patch_params(&self) -> PatchParams {
+        let params = PatchParams::apply(&self.field_manager);
+        if self.dry_run {
+            return params.dry_run();
+        }
+        params
+    }
+
+    /// The same settings for functions that create rather than apply
+    pub fn post_params(&self) -> PostParams {
+        PostParams {
+            dry_run: self.dry_run,
+            field_manager: Some(self.field_manager.clone()),
+        }
+    }
+}
+
 impl Error {
+    /// Variant name suitable as a metric label. Deliberately ignores the
+    /// contained data so that label cardinality stays bounded.
     pub fn metric_label(&self) -> String {
-        format!("{self:?}").to_lowercase()
+        match self {
+            Error::SerializationError { .. } => "serialization_error",
+            Error::KubeError { .. } => "kube_error",
+            Error::FinalizerError(_) => "finalizer_error",
+            Error::IllegalDocument => "illegal_document",
+            Error::IPTimeout => "ip_timeout",
+            Error::IngressListEmpty => "ingress_list_empty",
+            Error::IngressListMissing => "ingress_list_missing",
+            Error::JoinError { .. } => "join_error",
+            Error::WaitError { .. } => "wait_error",
+            Error::WaitTimeout { .. } => "wait_timeout",
+            Error::RandomNumberError { .. } => "random_number_error",
+            Error::MissingNodeInputs(_) => "missing_node_inputs",
+            Error::DecodeKeyError(_) => "decode_key_error",
+            Error::ConfigMapNotFound { .. } => "config_map_not_found",
+            Error::ConfigMapMissingData { .. } => "config_map_missing_data",
+            Error::ConfigMapMissingKey { .. } => "config_map_missing_key",
+            Error::SecretMapError(_) => "secret_map_error",
+            Error::StatefulSetError(_) => "stateful_set_error",
+            Error::ServiceError(_) => "service_error",
+            Error::ExternalAddressMissing(_) => "external_address_missing",
+            Error::InvalidNodePort(_) => "invalid_node_port",
+            Error::InvalidCidr(_) => "invalid_cidr",
+            Error::InvalidLabel(_) => "invalid_label",
+            Error::DuplicatePortName(_) => "duplicate_port_name",
+            Error::MissingPortName => "missing_port_name",
+            Error::Cancelled => "cancelled",
+            Error::LoadBalancerOnly(_) => "load_balancer_only",
+            Error::HealthCheckNodePortRequiresLocal => "health_check_node_port_requires_local",
+            Error::InvalidMultiaddr(_) => "invalid_multiaddr",
+            Error::InvalidHpaRange(..) => "invalid_hpa_range",
+            Error::ContainerNotFound(_) => "container_not_found",
+            Error::InvalidQuantity(_) => "invalid_quantity",
+            Error::UnknownTargetPort(_) => "unknown_target_port",
+            Error::InvalidNameTemplate(_) => "invalid_name_template",
+            Error::InvalidRoleRefKind(_) => "invalid_role_ref_kind",
+            Error::LoadBalancerFailed(_) => "load_balancer_failed",
+            Error::ClientIpAffinityOnly => "client_ip_affinity_only",
+            Error::UnrestrictedNetworkPolicy => "unrestricted_network_policy",
+            Error::NegativeReplicas(_) => "negative_replicas",
+        }
+        .to_owned()
+    }
+
+    /// Whether a retry is likely to succeed, so reconcilers can requeue
+    /// quickly instead of backing off. Transient errors are:
+    /// - API responses 409 (conflict), 429 (rate limited), 500 and 503
+    /// - `IPTimeout` and `WaitTimeout`
+    ///
+    /// Everything else is treated as permanent.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::KubeError {
+                source: kube::Error::Api(status),
+            } => matches!(status.code, 409 | 429 | 500 | 503),
+            Error::IPTimeout | Error::WaitTimeout { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+/// Operator identity stamped onto objects by `labels`. Operators should build
+/// this from their own metadata, e.g.
+/// `LabelConfig::new(env!("CARGO_PKG_VERSION"), "my-operator")`.
+#[derive(Debug, Clone)]
+pub struct LabelConfig {
+    pub version: String,
+    pub managed_by: String,
+}
+
+impl Default for LabelConfig {
+    fn default() -> Self {
+        Self::new(env!("CARGO_PKG_VERSION"), DEFAULT_FIELD_MANAGER)
     }
 }
 
+impl LabelConfig {
+    pub fn new(version: &str, managed_by: &str) -> Self {
+        Self {
+            version: version.to_owned(),
+            managed_by: managed_by.to_owned(),
+        }
+    }
+
+    #[instrument]
+    pub fn labels(&self, name: String, kind: String) -> Result<BTreeMap<String, String>> {
+        validate_label_value(&self.version)?;
+        validate_label_value(&self.managed_by)?;
+
+        let mut labels = selector_labels(name, kind)?;
+        labels.insert("app.kubernetes.io/version".to_owned(), self.version.clone());
+        labels.insert(
+            "app.kubernetes.io/managed-by".to_owned(),
+            self.managed_by.clone(),
+        );
+        Ok(labels)
+    }
+}
+
+/// Builds the recommended `app.kubernetes.io/*` label set plus any extras,
+/// e.g. `Labels::new("ipfs", "storage").component("gateway").with("team", "infra").build()`
+#[derive(Debug, Clone)]
+pub struct Labels {
+    name: String,
+    kind: String,
+    config: LabelConfig,
+    extra: BTreeMap<String, String>,
+}
+
+impl Labels {
+    pub fn new(name: &str, kind: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            kind: kind.to_owned(),
+            config: LabelConfig::default(),
+            extra: BTreeMap::new(),
+        }
+    }
+
+    pub fn config(mut self, config: LabelConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn component(self, component: &str) -> Self {
+        self.with("app.kubernetes.io/component", component)
+    }
+
+    pub fn part_of(self, part_of: &str) -> Self {
+        self.with("app.kubernetes.io/part-of", part_of)
+    }
+
+    pub fn with(mut self, key: &str, value: &str) -> Self {
+        self.extra.insert(key.to_owned(), value.to_owned());
+        self
+    }
+
+    /// Fails with `Error::InvalidLabel` if any value would be rejected by the
+    /// API server
+    pub fn build(self) -> Result<BTreeMap<String, String>> {
+        let mut labels = self.config.labels(self.name, self.kind)?;
+        for value in self.extra.values() {
+            validate_label_value(value)?;
+        }
+        labels.extend(self.extra);
+        Ok(labels)
+    }
+}
+
+/// Checks a label value against the API server's rules: at most 63
+/// characters of `[A-Za-z0-9_.-]`, beginning and ending with an alphanumeric.
+/// Empty values are allowed.
 #[instrument]
-pub fn labels(name: String, kind: String) -> BTreeMap<String, String> {
-    let mut labels = selector_labels(name, kind);
-    labels.insert("app.kubernetes.io/version".to_owned(), "0.2.0".to_owned());
-    labels.insert(
-        "app.kubernetes.io/managed-by".to_owned(),
-        "ipfs-operator".to_owned(),
-    );
-    labels
+pub fn validate_label_value(value: &str) -> Result<()> {
+    let bytes = value.as_bytes();
+    let valid = value.is_empty()
+        || (value.len() <= 63
+            && bytes[0].is_ascii_alphanumeric()
+            && bytes[bytes.len() - 1].is_ascii_alphanumeric()
+            && bytes
+                .iter()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.')));
+
+    if !valid {
+        return Err(Error::InvalidLabel(value.to_owned()));
+    }
+    Ok(())
+}
+
+/// Labels using `LabelConfig::default()`, i.e. this crate's version and
+/// `ipfs-operator`
+#[instrument]
+pub fn labels(name: String, kind: String) -> Result<BTreeMap<String, String>> {
+    LabelConfig::default().labels(name, kind)
 }
 
 #[instrument]
-pub fn selector_labels(name: String, kind: String) -> BTreeMap<String, String> {
+pub fn selector_labels(name: String, kind: String) -> Result<BTreeMap<String, String>> {
+    let app_name = format!("ipfs-{kind}-cluster");
+    validate_label_value(&app_name)?;
+    validate_label_value(&name)?;
+
     let mut labels: BTreeMap<String, String> = BTreeMap::new();
-    labels.insert(
-        "app.kubernetes.io/name".to_owned(),
-        format!("ipfs-{kind}-cluster"),
-    );
+    labels.insert("app.kubernetes.io/name".to_owned(), app_name);
     labels.insert("app.kubernetes.io/instance".to_owned(), name.to_owned());
-    labels
+    Ok(labels)
+}
+
+/// Outcome of a `delete`, so reconcilers know whether to requeue and wait
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeleteResult {
+    /// The object is gone
+    Deleted,
+    /// The API server accepted the delete but the object still exists, e.g.
+    /// while finalizers run or dependents are removed in the foreground
+    Deleting,
+    /// There was nothing to delete
+    NotFound,
+}
+
+impl<K> From<Either<K, Status>> for DeleteResult {
+    fn from(res: Either<K, Status>) -> Self {
+        match res {
+            Either::Left(_) => DeleteResult::Deleting,
+            Either::Right(_) => DeleteResult::Deleted,
+        }
+    }
+}
+
+/// Maps a NotFound API error to `Ok(None)`, leaving other errors alone.
+/// Goes by the 404 status code since proxies and webhooks that rewrite error
+/// bodies don't always keep the `reason`.
+pub(crate) fn ignore_not_found<T>(
+    result: std::result::Result<T, kube::Error>,
+) -> std::result::Result<Option<T>, kube::Error> {
+    match result {
+        Ok(res) => Ok(Some(res)),
+        Err(kube::Error::Api(er)) if er.code == 404 || er.reason == "NotFound" => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Builds a controller owner reference to `obj`, so child resources deployed
+/// with it are garbage-collected when `obj` is deleted. Returns `None` if
+/// `obj` has no name or UID yet.
+#[instrument(skip(obj))]
+pub fn owner_reference<K: Resource<DynamicType = ()>>(obj: &K) -> Option<OwnerReference> {
+    obj.controller_owner_ref(&())
+}
+
+/// Waits until the named object is gone (or replaced by one with a new UID).
+/// Returns immediately if it doesn't exist.
+#[instrument(skip(api))]
+pub async fn wait_for_deletion<K>(api: Api<K>, name: &str, timeout: Duration) -> Result<()>
+where
+    K: Resource + Clone + Debug + DeserializeOwned + Send + 'static,
+{
+    let Some(uid) = api
+        .get_opt(name)
+        .await?
+        .and_then(|obj| obj.meta().uid.clone())
+    else {
+        return Ok(());
+    };
+
+    let deleted = await_condition(api, name, is_deleted(&uid));
+    tokio::time::timeout(timeout, deleted).await??;
+    Ok(())
+}
+
+/// Runs `apply` or `cleanup` for `obj` through `kube::runtime::finalizer`,
+/// which adds `finalizer_name` before the first `apply` and removes it once
+/// `cleanup` succeeds. Errors (including those from `apply`/`cleanup`) come
+/// back as `Error::FinalizerError`, already boxed to break the type cycle.
+#[instrument(skip(api, obj, apply, cleanup))]
+pub async fn finalize<K, A, AFut, C, CFut>(
+    api: &Api<K>,
+    finalizer_name: &str,
+    obj: Arc<K>,
+    apply: A,
+    cleanup: C,
+) -> Result<Action>
+where
+    K: Resource + Clone + DeserializeOwned + Serialize + Debug,
+    A: FnOnce(Arc<K>) -> AFut,
+    AFut: Future<Output = Result<Action>>,
+    C: FnOnce(Arc<K>) -> CFut,
+    CFut: Future<Output = Result<Action>>,
+{
+    finalizer(api, finalizer_name, obj, |event| async move {
+        match event {
+            FinalizerEvent::Apply(obj) => apply(obj).await,
+            FinalizerEvent::Cleanup(obj) => cleanup(obj).await,
+        }
+    })
+    .await
+    .map_err(|e| Error::FinalizerError(Box::new(e)))
 }
 
 #[instrument]
 pub fn external_address_name(name: &str) -> String {
     format!("{name}-external-addresses")
 }
+
+#[cfg(test)]
+mod tests {
+    use kube::core::Status;
+
+    use super::*;
+
+    #[test]
+    fn metric_label_ignores_the_error_source() {
+        let not_found = Error::from(kube::Error::Api(
+            Status::failure("services \"a\" not found", "NotFound")
+                .with_code(404)
+                .boxed(),
+        ));
+        let forbidden = Error::from(kube::Error::Api(
+            Status::failure("services is forbidden", "Forbidden")
+                .with_code(403)
+                .boxed(),
+        ));
+        assert_eq!(not_found.metric_label(), "kube_error");
+        assert_eq!(not_found.metric_label(), forbidden.metric_label());
+    }
+
+    #[test]
+    fn label_values_must_fit_kubernetes_rules() {
+        validate_label_value("ipfs-node-0").unwrap();
+        validate_label_value("").unwrap();
+        validate_label_value(&"a".repeat(63)).unwrap();
+
+        let too_long = "a".repeat(64);
+        assert!(matches!(
+            validate_label_value(&too_long),
+            Err(Error::InvalidLabel(value)) if value == too_long
+        ));
+        assert!(matches!(
+            selector_labels("-ipfs".to_owned(), "node".to_owned()),
+            Err(Error::InvalidLabel(value)) if value == "-ipfs"
+        ));
+    }
+}
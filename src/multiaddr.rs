@@ -0,0 +1,102 @@
+use crate::types::service::{IpFamily, Protocol};
+use crate::{Error, Result};
+
+/// Formats `address` as a libp2p multiaddr, picking `/ip4`, `/ip6` or `/dns4`
+/// from its form, e.g. `/ip4/1.2.3.4/tcp/4001` or `/dns4/lb.example.com/udp/4001`
+pub fn build_multiaddr(address: &str, port: i32, protocol: Protocol) -> Result<String> {
+    if address.is_empty() || address.contains('/') {
+        return Err(Error::InvalidMultiaddr(address.to_owned()));
+    }
+    if !(1..=65535).contains(&port) {
+        return Err(Error::InvalidMultiaddr(format!("{address} port {port}")));
+    }
+
+    let host = match IpFamily::of(address) {
+        Some(IpFamily::Ipv4) => "ip4",
+        Some(IpFamily::Ipv6) => "ip6",
+        None => "dns4",
+    };
+    let transport = protocol.as_str().to_lowercase();
+    Ok(format!("/{host}/{address}/{transport}/{port}"))
+}
+
+/// Splits a multiaddr from `build_multiaddr` back into address, port and
+/// protocol. Trailing components such as `/p2p/<peer id>` are ignored.
+pub fn parse_multiaddr(multiaddr: &str) -> Result<(String, i32, Protocol)> {
+    let invalid = || Error::InvalidMultiaddr(multiaddr.to_owned());
+
+    let mut parts = multiaddr.split('/');
+    if parts.next() != Some("") {
+        return Err(invalid());
+    }
+
+    let (host, address) = (parts.next(), parts.next());
+    let address = match (host, address) {
+        (Some("ip4"), Some(address)) if IpFamily::of(address) == Some(IpFamily::Ipv4) => address,
+        (Some("ip6"), Some(address)) if IpFamily::of(address) == Some(IpFamily::Ipv6) => address,
+        (Some("dns" | "dns4" | "dns6"), Some(address)) if !address.is_empty() => address,
+        _ => return Err(invalid()),
+    };
+
+    let protocol = match parts.next() {
+        Some("tcp") => Protocol::Tcp,
+        Some("udp") => Protocol::Udp,
+        Some("sctp") => Protocol::Sctp,
+        _ => return Err(invalid()),
+    };
+
+    let port = parts
+        .next()
+        .and_then(|port| port.parse::<u16>().ok())
+        .ok_or_else(invalid)?;
+
+    Ok((address.to_owned(), i32::from(port), protocol))
+}
+
+pub fn port_from_multiaddr(multiaddr: &str) -> Result<i32> {
+    parse_multiaddr(multiaddr).map(|(_, port, _)| port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ipv4_round_trips() {
+        let addr = build_multiaddr("203.0.113.7", 4001, Protocol::Tcp).unwrap();
+        assert_eq!(addr, "/ip4/203.0.113.7/tcp/4001");
+        assert_eq!(
+            parse_multiaddr(&addr).unwrap(),
+            ("203.0.113.7".to_owned(), 4001, Protocol::Tcp)
+        );
+    }
+
+    #[test]
+    fn ipv6_round_trips() {
+        let addr = build_multiaddr("2001:db8::7", 4001, Protocol::Udp).unwrap();
+        assert_eq!(addr, "/ip6/2001:db8::7/udp/4001");
+        assert_eq!(
+            parse_multiaddr(&addr).unwrap(),
+            ("2001:db8::7".to_owned(), 4001, Protocol::Udp)
+        );
+    }
+
+    #[test]
+    fn hostnames_use_dns4() {
+        let addr = build_multiaddr("lb.example.com", 4001, Protocol::Tcp).unwrap();
+        assert_eq!(addr, "/dns4/lb.example.com/tcp/4001");
+        assert_eq!(
+            port_from_multiaddr(&format!("{addr}/p2p/QmPeer")).unwrap(),
+            4001
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_multiaddrs() {
+        assert!(build_multiaddr("", 4001, Protocol::Tcp).is_err());
+        assert!(build_multiaddr("203.0.113.7", 0, Protocol::Tcp).is_err());
+        assert!(parse_multiaddr("/ip4/lb.example.com/tcp/4001").is_err());
+        assert!(parse_multiaddr("/ip4/203.0.113.7/quic/4001").is_err());
+        assert!(parse_multiaddr("ip4/203.0.113.7/tcp/4001").is_err());
+    }
+}
@@ -0,0 +1,108 @@
+use k8s_openapi::api::core::v1::ResourceRequirements;
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+use std::collections::BTreeMap;
+
+use crate::{Error, Result};
+
+const SUFFIXES: &[&str] = &[
+    "", "n", "u", "m", "k", "M", "G", "T", "P", "E", "Ki", "Mi", "Gi", "Ti", "Pi", "Ei",
+];
+
+/// Builds container requests and limits from quantity strings such as `500m`
+/// or `2Gi`, skipping any that are `None`
+pub fn resource_requirements(
+    cpu_request: Option<&str>,
+    memory_request: Option<&str>,
+    cpu_limit: Option<&str>,
+    memory_limit: Option<&str>,
+) -> Result<ResourceRequirements> {
+    let requests = quantities(cpu_request, memory_request)?;
+    let limits = quantities(cpu_limit, memory_limit)?;
+
+    Ok(ResourceRequirements {
+        requests: (!requests.is_empty()).then_some(requests),
+        limits: (!limits.is_empty()).then_some(limits),
+        ..ResourceRequirements::default()
+    })
+}
+
+/// Parses a Kubernetes quantity, e.g. `100m`, `1.5`, `512Mi` or `1e3`
+pub fn quantity(value: &str) -> Result<Quantity> {
+    if !is_quantity(value) {
+        return Err(Error::InvalidQuantity(value.to_owned()));
+    }
+    Ok(Quantity(value.to_owned()))
+}
+
+fn quantities(cpu: Option<&str>, memory: Option<&str>) -> Result<BTreeMap<String, Quantity>> {
+    let mut map = BTreeMap::new();
+    if let Some(cpu) = cpu {
+        map.insert("cpu".to_owned(), quantity(cpu)?);
+    }
+    if let Some(memory) = memory {
+        map.insert("memory".to_owned(), quantity(memory)?);
+    }
+    Ok(map)
+}
+
+fn is_quantity(value: &str) -> bool {
+    let unsigned = value.strip_prefix(['+', '-']).unwrap_or(value);
+    let number_len = unsigned
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(unsigned.len());
+    let (number, suffix) = unsigned.split_at(number_len);
+
+    let valid_number =
+        number.chars().any(|c| c.is_ascii_digit()) && number.matches('.').count() <= 1;
+    if !valid_number {
+        return false;
+    }
+
+    // `E` and `Ei` are suffixes; only an `e`/`E` followed by digits is a
+    // decimal exponent, e.g. `1e3` or `2E-2`
+    if SUFFIXES.contains(&suffix) {
+        return true;
+    }
+    match suffix.strip_prefix(['e', 'E']) {
+        Some(exponent) => {
+            let digits = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+            !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_suffixes_and_exponents() {
+        for value in [
+            "100m", "1.5", "512Mi", "2Gi", "1E", "1Ei", "1e3", "2E-2", "+1k",
+        ] {
+            assert!(quantity(value).is_ok(), "{value}");
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_quantities() {
+        for value in ["", "Mi", "1.2.3", "1e", "1E+", "1mi", "1 Gi", "1Eix"] {
+            assert!(
+                matches!(quantity(value), Err(Error::InvalidQuantity(v)) if v == value),
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn resource_requirements_skips_unset_quantities() {
+        let requirements = resource_requirements(Some("500m"), None, None, Some("2Gi")).unwrap();
+        let requests = requirements.requests.unwrap();
+        assert_eq!(requests.get("cpu"), Some(&Quantity("500m".to_owned())));
+        assert!(!requests.contains_key("memory"));
+        let limits = requirements.limits.unwrap();
+        assert_eq!(limits.get("memory"), Some(&Quantity("2Gi".to_owned())));
+        assert!(!limits.contains_key("cpu"));
+    }
+}
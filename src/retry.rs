@@ -0,0 +1,53 @@
+use rand::Rng;
+use std::time::Duration;
+use tokio::time::{Instant, sleep};
+use tracing::{instrument, warn};
+
+use crate::Result;
+
+/// Runs `f` until it succeeds or fails with an error that isn't
+/// `Error::is_transient`. Between attempts it sleeps for an exponentially
+/// growing, jittered delay starting at `base_delay`. Gives up with the last
+/// error after `max_retries` retries, or earlier if the next sleep would take
+/// the total time past `max_elapsed`.
+#[instrument(skip(f))]
+pub async fn with_backoff<F, Fut, T>(
+    mut f: F,
+    max_retries: u32,
+    base_delay: Duration,
+    max_elapsed: Duration,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let start = Instant::now();
+    let mut attempt: u32 = 0;
+
+    loop {
+        let err = match f().await {
+            Ok(res) => return Ok(res),
+            Err(e) => e,
+        };
+
+        if !err.is_transient() || attempt >= max_retries {
+            return Err(err);
+        }
+
+        // Equal jitter: somewhere between half and all of the exponential step
+        let step = base_delay.saturating_mul(2u32.saturating_pow(attempt));
+        let delay = step.mul_f64(rand::thread_rng().gen_range(0.5..=1.0));
+        if start.elapsed() + delay > max_elapsed {
+            return Err(err);
+        }
+
+        warn!(
+            error = err.to_string(),
+            attempt,
+            delay_ms = delay.as_millis() as u64,
+            "Retrying after transient error"
+        );
+        sleep(delay).await;
+        attempt += 1;
+    }
+}
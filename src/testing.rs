@@ -0,0 +1,161 @@
+//! A `kube::Client` backed by `tower_test::mock`, for exercising the helpers
+//! in this crate without a cluster:
+//!
+//! ```
+//! # use operator_common::{ApplyOptions, testing, types::configmap};
+//! # use serde_json::json;
+//! # use std::collections::BTreeMap;
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let (client, mut server) = testing::mock_client();
+//! let data = BTreeMap::from([("key".to_owned(), "value".to_owned())]);
+//! let deploy = tokio::spawn(async move {
+//!     let apply = ApplyOptions::default();
+//!     configmap::deploy(client, "cm", "ns", data, BTreeMap::new(), Default::default(), &apply)
+//!         .await
+//! });
+//! let body = server
+//!     .expect_patch(
+//!         "/api/v1/namespaces/ns/configmaps/cm",
+//!         json!({ "apiVersion": "v1", "kind": "ConfigMap", "metadata": { "name": "cm" } }),
+//!     )
+//!     .await;
+//! assert_eq!(body["data"]["key"], "value");
+//! deploy.await??;
+//! # Ok(())
+//! # }
+//! ```
+
+use http::{Method, Request, Response, StatusCode};
+use kube::Client;
+use kube::client::Body;
+use serde_json::Value;
+use tower_test::mock::{self, Handle, SendResponse};
+
+/// The other end of a `mock_client`, answering its requests in order
+pub struct MockServer {
+    handle: Handle<Request<Body>, Response<Body>>,
+}
+
+/// A request taken by `MockServer::receive` that has not been answered yet
+pub struct PendingRequest {
+    pub method: Method,
+    pub path: String,
+    pub query: String,
+    /// The JSON body, `Null` when empty
+    pub body: Value,
+    send: SendResponse<Response<Body>>,
+}
+
+impl PendingRequest {
+    pub fn respond(self, status: StatusCode, respond_with: Value) {
+        let response = Response::builder()
+            .status(status)
+            .body(Body::from(
+                serde_json::to_vec(&respond_with).expect("response serializes"),
+            ))
+            .expect("response is valid");
+        self.send.send_response(response);
+    }
+}
+
+/// Builds a client whose requests are answered through the returned server.
+/// The client's default namespace is `default`.
+pub fn mock_client() -> (Client, MockServer) {
+    let (service, handle) = mock::pair::<Request<Body>, Response<Body>>();
+    (Client::new(service, "default"), MockServer { handle })
+}
+
+impl MockServer {
+    /// Waits for the next request, asserts its method and path, answers with
+    /// `status` and `respond_with`, and returns the request body (`Null` when
+    /// empty) for the caller to inspect
+    pub async fn expect(
+        &mut self,
+        method: Method,
+        path: &str,
+        status: StatusCode,
+        respond_with: Value,
+    ) -> Value {
+        self.expect_with_query(method, path, status, respond_with)
+            .await
+            .1
+    }
+
+    /// Like `expect`, but also returns the request's query string, e.g. to
+    /// check the selectors of a list or `dryRun=All`
+    pub async fn expect_with_query(
+        &mut self,
+        method: Method,
+        path: &str,
+        status: StatusCode,
+        respond_with: Value,
+    ) -> (String, Value) {
+        let request = self.receive().await;
+        assert_eq!(request.method, method, "unexpected method for {path}");
+        assert_eq!(request.path, path);
+        let (query, body) = (request.query.clone(), request.body.clone());
+        request.respond(status, respond_with);
+        (query, body)
+    }
+
+    /// Waits for the next request without answering it, e.g. to check that
+    /// several requests are in flight at once
+    pub async fn receive(&mut self) -> PendingRequest {
+        let (request, send) = self
+            .handle
+            .next_request()
+            .await
+            .expect("client did not send a request");
+        let method = request.method().clone();
+        let path = request.uri().path().to_owned();
+        let query = request.uri().query().unwrap_or_default().to_owned();
+
+        let body = request
+            .into_body()
+            .collect_bytes()
+            .await
+            .expect("request body is readable");
+        let body = match body.is_empty() {
+            true => Value::Null,
+            false => serde_json::from_slice(&body).expect("request body is JSON"),
+        };
+
+        PendingRequest {
+            method,
+            path,
+            query,
+            body,
+            send,
+        }
+    }
+
+    pub async fn expect_get(&mut self, path: &str, respond_with: Value) -> Value {
+        self.expect(Method::GET, path, StatusCode::OK, respond_with)
+            .await
+    }
+
+    pub async fn expect_patch(&mut self, path: &str, respond_with: Value) -> Value {
+        self.expect(Method::PATCH, path, StatusCode::OK, respond_with)
+            .await
+    }
+
+    pub async fn expect_delete(&mut self, path: &str, respond_with: Value) -> Value {
+        self.expect(Method::DELETE, path, StatusCode::OK, respond_with)
+            .await
+    }
+
+    /// Answers the next request with a `NotFound` status
+    pub async fn expect_not_found(&mut self, method: Method, path: &str) -> Value {
+        let status = serde_json::json!({
+            "kind": "Status",
+            "apiVersion": "v1",
+            "status": "Failure",
+            "message": format!("{path} not found"),
+            "reason": "NotFound",
+            "code": 404,
+        });
+        self.expect(method, path, StatusCode::NOT_FOUND, status)
+            .await
+    }
+}
@@ -1,8 +1,28 @@
+use k8s_openapi::ByteString;
 use k8s_openapi::api::core::v1::ConfigMap;
-use kube::api::{DeleteParams, ObjectMeta, Patch, PatchParams};
-use kube::{Api, Client, Error};
-use std::collections::BTreeMap;
-use tracing::{Level, event, instrument};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use kube::api::{DeleteParams, ListParams, ObjectMeta, Patch, PatchParams, PropagationPolicy};
+use kube::{Api, Client, Error, ResourceExt};
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use tokio::task::JoinSet;
+use tracing::{Instrument, Level, error, event, instrument};
+
+use crate::{ApplyOptions, DeleteResult, diff, ignore_not_found};
+
+/// Optional ConfigMap settings beyond the data and labels
+#[derive(Debug, Default, Clone)]
+pub struct ConfigMapOptions {
+    /// Lets the ConfigMap be garbage-collected along with its owner
+    pub owner: Option<OwnerReference>,
+    /// Marks the ConfigMap `immutable`, so kubelet stops watching it. Once set
+    /// the data can no longer change and the flag cannot be cleared by apply;
+    /// the API server rejects such patches and the caller must delete and
+    /// recreate the ConfigMap instead.
+    pub immutable: bool,
+}
 
 #[instrument(skip(client))]
 pub async fn deploy(
@@ -11,47 +31,290 @@ pub async fn deploy(
     namespace: &str,
     data: BTreeMap<String, String>,
     labels: BTreeMap<String, String>,
+    options: ConfigMapOptions,
+    apply_options: &ApplyOptions,
+) -> Result<ConfigMap, Error> {
+    deploy_binary(
+        client,
+        name,
+        namespace,
+        data,
+        BTreeMap::new(),
+        labels,
+        options,
+        apply_options,
+    )
+    .await
+}
+
+/// Like `deploy`, but also sets `binaryData` for non-UTF-8 content such as
+/// swarm keys or compressed configs. Both maps are applied together.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(client, binary_data))]
+pub async fn deploy_binary(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    data: BTreeMap<String, String>,
+    binary_data: BTreeMap<String, ByteString>,
+    labels: BTreeMap<String, String>,
+    options: ConfigMapOptions,
+    apply_options: &ApplyOptions,
 ) -> Result<ConfigMap, Error> {
-    let object: ConfigMap = ConfigMap {
+    let object = build(name, namespace, data, binary_data, labels, options);
+
+    event!(Level::INFO, name, namespace, "Creating ConfigMap");
+
+    // Create the pvc defined above
+    let service_api: Api<ConfigMap> = Api::namespaced(client, namespace);
+    let params = apply_options.patch_params();
+    service_api
+        .patch(name, &params, &Patch::Apply(&object))
+        .await
+}
+
+fn build(
+    name: &str,
+    namespace: &str,
+    data: BTreeMap<String, String>,
+    binary_data: BTreeMap<String, ByteString>,
+    labels: BTreeMap<String, String>,
+    options: ConfigMapOptions,
+) -> ConfigMap {
+    ConfigMap {
         data: Some(data),
+        binary_data: (!binary_data.is_empty()).then_some(binary_data),
+        immutable: options.immutable.then_some(true),
         metadata: ObjectMeta {
             name: Some(name.to_owned()),
             namespace: Some(namespace.to_owned()),
             labels: Some(labels.clone()),
+            owner_references: options.owner.map(|owner| vec![owner]),
             ..ObjectMeta::default()
         },
-        ..ConfigMap::default()
-    };
+    }
+}
+
+/// Like `deploy`, but first reads the live ConfigMap and skips the apply when
+/// `diff::needs_update` finds nothing to change, so watchers don't see a new
+/// resourceVersion. Returns whether anything was written; a missing ConfigMap
+/// is created and counts as changed.
+#[instrument(skip(client))]
+pub async fn deploy_if_changed(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    data: BTreeMap<String, String>,
+    labels: BTreeMap<String, String>,
+    options: ConfigMapOptions,
+    apply_options: &ApplyOptions,
+) -> Result<bool, crate::Error> {
+    let service_api: Api<ConfigMap> = Api::namespaced(client.clone(), namespace);
+    if let Some(existing) = service_api.get_opt(name).await? {
+        let desired = build(
+            name,
+            namespace,
+            data.clone(),
+            BTreeMap::new(),
+            labels.clone(),
+            options.clone(),
+        );
+        let changed = diff::changed_fields(&desired, &existing);
+        if changed.is_empty() {
+            event!(Level::DEBUG, name, namespace, "ConfigMap unchanged");
+            return Ok(false);
+        }
+        event!(
+            Level::DEBUG,
+            name,
+            namespace,
+            changed = changed.join(", "),
+            "ConfigMap changed"
+        );
+    }
+
+    deploy(
+        client,
+        name,
+        namespace,
+        data,
+        labels,
+        options,
+        apply_options,
+    )
+    .await?;
+    Ok(true)
+}
+
+/// Creates the ConfigMap only if it doesn't exist yet, e.g. to seed a
+/// default config that users may then edit. An existing ConfigMap is returned
+/// untouched, whereas `deploy` would revert such edits.
+#[instrument(skip(client))]
+pub async fn ensure(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    data: BTreeMap<String, String>,
+    labels: BTreeMap<String, String>,
+    apply_options: &ApplyOptions,
+) -> Result<ConfigMap, Error> {
+    let service_api: Api<ConfigMap> = Api::namespaced(client, namespace);
+    if let Some(existing) = service_api.get_opt(name).await? {
+        return Ok(existing);
+    }
+
+    let object = build(
+        name,
+        namespace,
+        data,
+        BTreeMap::new(),
+        labels,
+        ConfigMapOptions::default(),
+    );
 
     event!(Level::INFO, name, namespace, "Creating ConfigMap");
 
-    // Create the pvc defined above
+    match service_api
+        .create(&apply_options.post_params(), &object)
+        .await
+    {
+        // Someone else created it since the read; theirs wins
+        Err(Error::Api(er)) if er.reason == "AlreadyExists" => service_api.get(name).await,
+        res => res,
+    }
+}
+
+/// Adds or updates only the given keys, leaving keys written by other
+/// controllers in place (unlike `deploy`, which prunes them)
+#[instrument(skip(client))]
+pub async fn merge_data(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    patch: BTreeMap<String, String>,
+) -> Result<ConfigMap, Error> {
+    event!(Level::INFO, name, namespace, "Merging ConfigMap data");
+
     let service_api: Api<ConfigMap> = Api::namespaced(client, namespace);
-    let params = PatchParams::apply(name);
+    let patch = json!({ "data": patch });
     service_api
-        .patch(name, &params, &Patch::Apply(&object))
+        .patch(name, &PatchParams::default(), &Patch::Strategic(&patch))
         .await
 }
 
+/// Stable SHA-256 hex digest of the data. Stamping it onto a pod template
+/// annotation (e.g. `config-hash`) rolls the pods whenever the config changes.
+pub fn data_hash(data: &BTreeMap<String, String>) -> String {
+    let mut hasher = Sha256::new();
+    // BTreeMap iterates in sorted key order; length prefixes keep entries
+    // from running into each other
+    for (key, value) in data {
+        hasher.update((key.len() as u64).to_be_bytes());
+        hasher.update(key.as_bytes());
+        hasher.update((value.len() as u64).to_be_bytes());
+        hasher.update(value.as_bytes());
+    }
+    format!("{:x}", hasher.finalize())
+}
+
 #[instrument(skip(client))]
-pub async fn delete(client: Client, name: String, namespace: String) -> Result<(), Error> {
+pub async fn delete(
+    client: Client,
+    name: String,
+    namespace: String,
+    propagation_policy: Option<PropagationPolicy>,
+) -> Result<DeleteResult, Error> {
     event!(Level::INFO, name, namespace, "Deleting ConfigMap");
 
     let api: Api<ConfigMap> = Api::namespaced(client, namespace.as_str());
-    match api.delete(name.as_str(), &DeleteParams::default()).await {
-        Ok(_) => Ok(()),
-        Err(e) => {
-            match e {
-                // If the resource doesn't exist, we can ignore the error
-                Error::Api(er) => {
-                    if er.reason == "NotFound" {
-                        return Ok(());
-                    };
-                    Err(Error::Api(er))
-                }
-                _ => Err(e),
+    let dp = DeleteParams {
+        propagation_policy,
+        ..DeleteParams::default()
+    };
+    match ignore_not_found(api.delete(name.as_str(), &dp).await)? {
+        Some(res) => Ok(DeleteResult::from(res)),
+        None => Ok(DeleteResult::NotFound),
+    }
+}
+
+#[instrument(skip(client))]
+pub async fn list(
+    client: Client,
+    namespace: &str,
+    label_selector: &str,
+) -> Result<Vec<ConfigMap>, Error> {
+    let service_api: Api<ConfigMap> = Api::namespaced(client, namespace);
+    let lp = ListParams::default().labels(label_selector);
+    Ok(service_api.list(&lp).await?.items)
+}
+
+/// Deletes every ConfigMap matching `label_selector` concurrently, returning
+/// the outcome per name. Every delete is attempted even if some fail; the
+/// first failure is then returned.
+#[instrument(skip(client))]
+pub async fn delete_all(
+    client: Client,
+    namespace: &str,
+    label_selector: &str,
+) -> Result<BTreeMap<String, DeleteResult>, crate::Error> {
+    let configmaps = list(client.clone(), namespace, label_selector).await?;
+    delete_each(client, namespace, configmaps).await
+}
+
+/// Like `delete_all`, but spares the ConfigMaps named in `keep`, e.g. to drop
+/// per-index ConfigMaps left behind when an instance scales down
+#[instrument(skip(client))]
+pub async fn prune(
+    client: Client,
+    namespace: &str,
+    label_selector: &str,
+    keep: &BTreeSet<String>,
+) -> Result<BTreeMap<String, DeleteResult>, crate::Error> {
+    let stale = list(client.clone(), namespace, label_selector)
+        .await?
+        .into_iter()
+        .filter(|cm| !keep.contains(&cm.name_any()));
+    delete_each(client, namespace, stale).await
+}
+
+async fn delete_each(
+    client: Client,
+    namespace: &str,
+    configmaps: impl IntoIterator<Item = ConfigMap>,
+) -> Result<BTreeMap<String, DeleteResult>, crate::Error> {
+    let mut set = JoinSet::new();
+    for cm in configmaps {
+        let cli = client.clone();
+        let name = cm.name_any();
+        let ns = namespace.to_owned();
+
+        set.spawn(
+            async move {
+                let res = delete(cli, name.clone(), ns, None).await;
+                (name, res)
             }
-        }
+            .in_current_span(),
+        );
+    }
+
+    let mut results = BTreeMap::new();
+    let mut first_err: Option<crate::Error> = None;
+    while let Some(res) = set.join_next().await {
+        let err = match res {
+            Ok((name, Ok(result))) => {
+                results.insert(name, result);
+                continue;
+            }
+            Ok((_, Err(e))) => crate::Error::from(e),
+            Err(e) => crate::Error::from(e),
+        };
+        error!(error = err.to_string());
+        first_err.get_or_insert(err);
+    }
+
+    match first_err {
+        Some(err) => Err(err),
+        None => Ok(results),
     }
 }
 
@@ -66,17 +329,37 @@ pub async fn get_data(
     let default_config = match service_api.get_opt(name).await? {
         Some(res) => res,
         None => {
-            return Err(crate::Error::ConfigMapError(format!(
-                "ConfigMap {name} not found"
-            )));
+            return Err(crate::Error::ConfigMapNotFound {
+                name: name.to_owned(),
+                namespace: namespace.to_owned(),
+            });
         }
     };
 
     match default_config.data {
         Some(c) => Ok(c),
-        None => Err(crate::Error::ConfigMapError(
-            "ConfigMap missing data".to_string(),
-        )),
+        None => Err(crate::Error::ConfigMapMissingData {
+            name: name.to_owned(),
+        }),
+    }
+}
+
+/// Fetches the ConfigMap and deserializes the JSON stored under `key`
+#[instrument(skip(client))]
+pub async fn get_value<T: DeserializeOwned>(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    key: &str,
+) -> Result<T, crate::Error> {
+    let data = get_data(client, name, namespace).await?;
+
+    match data.get(key) {
+        Some(value) => Ok(serde_json::from_str(value)?),
+        None => Err(crate::Error::ConfigMapMissingKey {
+            name: name.to_owned(),
+            key: key.to_owned(),
+        }),
     }
 }
 
@@ -95,8 +378,269 @@ pub async fn get_data_opt(
 
     match default_config.data {
         Some(c) => Ok(Some(c)),
-        None => Err(crate::Error::ConfigMapError(
-            "ConfigMap missing data".to_string(),
-        )),
+        None => Err(crate::Error::ConfigMapMissingData {
+            name: name.to_owned(),
+        }),
+    }
+}
+
+#[instrument(skip(client))]
+pub async fn get_binary_data_opt(
+    client: Client,
+    name: &str,
+    namespace: &str,
+) -> Result<Option<BTreeMap<String, ByteString>>, crate::Error> {
+    let service_api: Api<ConfigMap> = Api::namespaced(client, namespace);
+
+    let default_config = match service_api.get_opt(name).await? {
+        Some(res) => res,
+        None => return Ok(None),
+    };
+
+    match default_config.binary_data {
+        Some(c) => Ok(Some(c)),
+        None => Err(crate::Error::ConfigMapMissingData {
+            name: name.to_owned(),
+        }),
+    }
+}
+
+/// Layers the `primary` ConfigMap (e.g. user overrides) over `fallback` (e.g.
+/// the defaults), with primary keys winning. Either one may be missing, but
+/// not both.
+#[instrument(skip(client))]
+pub async fn get_merged_data(
+    client: Client,
+    primary: &str,
+    fallback: &str,
+    namespace: &str,
+) -> Result<BTreeMap<String, String>, crate::Error> {
+    let service_api: Api<ConfigMap> = Api::namespaced(client, namespace);
+
+    let primary_config = service_api.get_opt(primary).await?;
+    let default_config = service_api.get_opt(fallback).await?;
+    if primary_config.is_none() && default_config.is_none() {
+        return Err(crate::Error::ConfigMapNotFound {
+            name: format!("{primary} or {fallback}"),
+            namespace: namespace.to_owned(),
+        });
+    }
+
+    let mut data = default_config.and_then(|c| c.data).unwrap_or_default();
+    data.extend(primary_config.and_then(|c| c.data).unwrap_or_default());
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::testing::mock_client;
+
+    fn configmap_json(name: &str, data: Value) -> Value {
+        json!({
+            "apiVersion": "v1",
+            "kind": "ConfigMap",
+            "metadata": { "name": name, "namespace": "ns" },
+            "data": data,
+        })
+    }
+
+    #[tokio::test]
+    async fn deploy_applies_data_and_labels() {
+        let (client, mut server) = mock_client();
+        let deploy = tokio::spawn(async move {
+            let data = BTreeMap::from([("config".to_owned(), "{}".to_owned())]);
+            let labels = BTreeMap::from([("app".to_owned(), "ipfs".to_owned())]);
+            deploy(
+                client,
+                "cm",
+                "ns",
+                data,
+                labels,
+                ConfigMapOptions::default(),
+                &ApplyOptions::default(),
+            )
+            .await
+        });
+
+        let (query, body) = server
+            .expect_with_query(
+                http::Method::PATCH,
+                "/api/v1/namespaces/ns/configmaps/cm",
+                http::StatusCode::OK,
+                configmap_json("cm", json!({ "config": "{}" })),
+            )
+            .await;
+        assert!(query.contains("fieldManager=ipfs-operator"), "{query}");
+        assert_eq!(body["metadata"]["name"], "cm");
+        assert_eq!(body["metadata"]["labels"], json!({ "app": "ipfs" }));
+        assert_eq!(body["data"], json!({ "config": "{}" }));
+
+        let cm = deploy.await.unwrap().unwrap();
+        assert_eq!(cm.name_any(), "cm");
+    }
+
+    #[tokio::test]
+    async fn binary_data_round_trips() {
+        let swarm_key = ByteString(vec![0x00, 0xff, 0x80, b'\n']);
+        let (client, mut server) = mock_client();
+        let deploy = tokio::spawn({
+            let client = client.clone();
+            let swarm_key = swarm_key.clone();
+            async move {
+                deploy_binary(
+                    client,
+                    "cm",
+                    "ns",
+                    BTreeMap::new(),
+                    BTreeMap::from([("swarm.key".to_owned(), swarm_key)]),
+                    BTreeMap::new(),
+                    ConfigMapOptions::default(),
+                    &ApplyOptions::default(),
+                )
+                .await
+            }
+        });
+
+        let mut stored = configmap_json("cm", json!({}));
+        let body = server
+            .expect_patch("/api/v1/namespaces/ns/configmaps/cm", stored.clone())
+            .await;
+        assert_eq!(body["binaryData"], json!({ "swarm.key": "AP+ACg==" }));
+        deploy.await.unwrap().unwrap();
+
+        stored["binaryData"] = body["binaryData"].clone();
+        let get = tokio::spawn(async move { get_binary_data_opt(client, "cm", "ns").await });
+        server
+            .expect_get("/api/v1/namespaces/ns/configmaps/cm", stored)
+            .await;
+        let binary_data = get.await.unwrap().unwrap().unwrap();
+        assert_eq!(binary_data["swarm.key"], swarm_key);
+    }
+
+    #[tokio::test]
+    async fn ensure_honours_dry_run() {
+        let (client, mut server) = mock_client();
+        let ensure = tokio::spawn(async move {
+            ensure(
+                client,
+                "cm",
+                "ns",
+                BTreeMap::new(),
+                BTreeMap::new(),
+                &ApplyOptions::default().dry_run(),
+            )
+            .await
+        });
+
+        server
+            .expect_not_found(http::Method::GET, "/api/v1/namespaces/ns/configmaps/cm")
+            .await;
+        let (query, _) = server
+            .expect_with_query(
+                http::Method::POST,
+                "/api/v1/namespaces/ns/configmaps",
+                http::StatusCode::CREATED,
+                configmap_json("cm", json!({})),
+            )
+            .await;
+        assert!(query.contains("dryRun=All"), "{query}");
+        ensure.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn merge_data_sends_only_the_merged_keys() {
+        let (client, mut server) = mock_client();
+        let merge = tokio::spawn(async move {
+            let patch = BTreeMap::from([("c".to_owned(), "3".to_owned())]);
+            merge_data(client, "cm", "ns", patch).await
+        });
+
+        // The server merges into the two keys seeded by other controllers
+        let merged = configmap_json("cm", json!({ "a": "1", "b": "2", "c": "3" }));
+        let body = server
+            .expect_patch("/api/v1/namespaces/ns/configmaps/cm", merged)
+            .await;
+        assert_eq!(body, json!({ "data": { "c": "3" } }));
+
+        let cm = merge.await.unwrap().unwrap();
+        let keys: Vec<_> = cm.data.unwrap().into_keys().collect();
+        assert_eq!(keys, ["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn delete_reports_finalizer_pending_deletion() {
+        let (client, mut server) = mock_client();
+        let delete = tokio::spawn(async move {
+            let deleting = delete(client.clone(), "cm".to_owned(), "ns".to_owned(), None).await;
+            let missing = delete(client, "gone".to_owned(), "ns".to_owned(), None).await;
+            (deleting, missing)
+        });
+
+        let mut pending = configmap_json("cm", json!({}));
+        pending["metadata"]["finalizers"] = json!(["example.com/cleanup"]);
+        pending["metadata"]["deletionTimestamp"] = json!("2024-01-01T00:00:00Z");
+        server
+            .expect(
+                http::Method::DELETE,
+                "/api/v1/namespaces/ns/configmaps/cm",
+                http::StatusCode::ACCEPTED,
+                pending,
+            )
+            .await;
+        server
+            .expect_not_found(
+                http::Method::DELETE,
+                "/api/v1/namespaces/ns/configmaps/gone",
+            )
+            .await;
+
+        let (deleting, missing) = delete.await.unwrap();
+        assert_eq!(deleting.unwrap(), DeleteResult::Deleting);
+        assert_eq!(missing.unwrap(), DeleteResult::NotFound);
+    }
+
+    #[tokio::test]
+    async fn prune_deletes_only_unwanted_configmaps() {
+        let (client, mut server) = mock_client();
+        let prune = tokio::spawn(async move {
+            let keep = BTreeSet::from(["inst-0".to_owned(), "inst-1".to_owned()]);
+            prune(client, "ns", "app.kubernetes.io/instance=inst", &keep).await
+        });
+
+        let existing = json!({
+            "apiVersion": "v1",
+            "kind": "ConfigMapList",
+            "metadata": {},
+            "items": [
+                configmap_json("inst-0", json!({})),
+                configmap_json("inst-1", json!({})),
+                configmap_json("inst-2", json!({})),
+            ],
+        });
+        let (query, _) = server
+            .expect_with_query(
+                http::Method::GET,
+                "/api/v1/namespaces/ns/configmaps",
+                http::StatusCode::OK,
+                existing,
+            )
+            .await;
+        assert!(
+            query.contains("labelSelector=app.kubernetes.io%2Finstance%3Dinst"),
+            "{query}"
+        );
+        let status = json!({ "kind": "Status", "apiVersion": "v1", "status": "Success" });
+        server
+            .expect_delete("/api/v1/namespaces/ns/configmaps/inst-2", status)
+            .await;
+
+        let deleted = prune.await.unwrap().unwrap();
+        assert_eq!(
+            deleted,
+            BTreeMap::from([("inst-2".to_owned(), DeleteResult::Deleted)])
+        );
     }
 }
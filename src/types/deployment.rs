@@ -0,0 +1,179 @@
+use k8s_openapi::api::apps::v1::{Deployment, DeploymentSpec};
+use k8s_openapi::api::core::v1::PodSpec;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use kube::api::{DeleteParams, ObjectMeta, Patch, PatchParams, PropagationPolicy};
+use kube::{Api, Client, Error};
+use kube_runtime::wait::{Condition, await_condition};
+use serde_json::json;
+use std::{collections::BTreeMap, time::Duration};
+use tracing::{Level, event, instrument};
+
+use crate::{ApplyOptions, ignore_not_found};
+
+/// Optional Deployment settings beyond the spec and labels. Set fields override
+/// the same fields in the spec passed to `deploy`.
+#[derive(Debug, Default, Clone)]
+pub struct DeploymentOptions {
+    /// Lets the Deployment be garbage-collected along with its owner
+    pub owner: Option<OwnerReference>,
+    /// Seconds a new pod must stay ready before it counts as available
+    pub min_ready_seconds: Option<i32>,
+    /// Pod `terminationGracePeriodSeconds`, for pods that need longer than
+    /// the 30 second default to shut down cleanly
+    pub termination_grace_period_seconds: Option<i64>,
+}
+
+/// Use `crate::labels` for `labels` and `crate::selector_labels` for the spec
+/// selector so the pods line up with the Services this crate manages
+#[instrument(skip(client))]
+pub async fn deploy(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    mut spec: DeploymentSpec,
+    labels: BTreeMap<String, String>,
+    options: DeploymentOptions,
+    apply_options: &ApplyOptions,
+) -> Result<Deployment, Error> {
+    if let Some(seconds) = options.min_ready_seconds {
+        spec.min_ready_seconds = Some(seconds);
+    }
+    if let Some(seconds) = options.termination_grace_period_seconds {
+        spec.template
+            .spec
+            .get_or_insert_with(PodSpec::default)
+            .termination_grace_period_seconds = Some(seconds);
+    }
+
+    let object: Deployment = Deployment {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            namespace: Some(namespace.to_owned()),
+            labels: Some(labels.clone()),
+            owner_references: options.owner.map(|owner| vec![owner]),
+            ..ObjectMeta::default()
+        },
+        spec: Some(spec),
+        ..Deployment::default()
+    };
+
+    event!(Level::INFO, name, namespace, "Creating Deployment");
+
+    let api: Api<Deployment> = Api::namespaced(client, namespace);
+    let params = apply_options.patch_params();
+    api.patch(name, &params, &Patch::Apply(&object)).await
+}
+
+#[instrument(skip(client))]
+pub async fn get_opt(
+    client: Client,
+    name: &str,
+    namespace: &str,
+) -> Result<Option<Deployment>, crate::Error> {
+    let api: Api<Deployment> = Api::namespaced(client, namespace);
+    Ok(api.get_opt(name).await?)
+}
+
+/// Patches only `spec.replicas`, leaving fields owned by other managers alone
+#[instrument(skip(client))]
+pub async fn scale(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    replicas: i32,
+) -> Result<Deployment, Error> {
+    event!(Level::INFO, name, namespace, replicas, "Scaling Deployment");
+
+    let api: Api<Deployment> = Api::namespaced(client, namespace);
+    let patch = json!({ "spec": { "replicas": replicas } });
+    api.patch(name, &PatchParams::default(), &Patch::Merge(&patch))
+        .await
+}
+
+/// Waits until every desired replica is available. Timeouts surface as
+/// `Error::WaitTimeout`.
+#[instrument(skip(client))]
+pub async fn wait_ready(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    timeout: Duration,
+) -> Result<(), crate::Error> {
+    let api: Api<Deployment> = Api::namespaced(client, namespace);
+
+    let ready = await_condition(api, name, is_ready());
+    tokio::time::timeout(timeout, ready).await??;
+    Ok(())
+}
+
+fn is_ready() -> impl Condition<Deployment> {
+    move |obj: Option<&Deployment>| {
+        if let Some(deployment) = &obj
+            && let Some(status) = &deployment.status
+            // Until the controller sees the latest spec, the status still
+            // counts the previous ReplicaSet's pods
+            && status.observed_generation.unwrap_or(0)
+                >= deployment.metadata.generation.unwrap_or(0)
+        {
+            // The API server defaults an unset replica count to 1
+            let desired = deployment
+                .spec
+                .as_ref()
+                .and_then(|s| s.replicas)
+                .unwrap_or(1);
+            return status.available_replicas.unwrap_or(0) == desired;
+        }
+        false
+    }
+}
+
+/// Use `PropagationPolicy::Foreground` to block until the ReplicaSets and
+/// pods are gone; `None` keeps the default background deletion
+#[instrument(skip(client))]
+pub async fn delete(
+    client: Client,
+    name: String,
+    namespace: String,
+    propagation_policy: Option<PropagationPolicy>,
+) -> Result<(), Error> {
+    event!(Level::INFO, name, namespace, "Deleting Deployment");
+
+    let api: Api<Deployment> = Api::namespaced(client, namespace.as_str());
+    let dp = DeleteParams {
+        propagation_policy,
+        ..DeleteParams::default()
+    };
+    // If the resource doesn't exist, we can ignore the error
+    ignore_not_found(api.delete(name.as_str(), &dp).await)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::apps::v1::DeploymentStatus;
+
+    use super::*;
+
+    #[test]
+    fn ready_waits_for_the_new_generation_to_be_observed() {
+        let mut deployment = Deployment {
+            metadata: ObjectMeta {
+                generation: Some(2),
+                ..ObjectMeta::default()
+            },
+            spec: Some(DeploymentSpec {
+                replicas: Some(2),
+                ..DeploymentSpec::default()
+            }),
+            status: Some(DeploymentStatus {
+                observed_generation: Some(1),
+                available_replicas: Some(2),
+                ..DeploymentStatus::default()
+            }),
+        };
+        assert!(!is_ready().matches_object(Some(&deployment)));
+
+        deployment.status.as_mut().unwrap().observed_generation = Some(2);
+        assert!(is_ready().matches_object(Some(&deployment)));
+    }
+}
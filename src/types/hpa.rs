@@ -0,0 +1,98 @@
+use k8s_openapi::api::autoscaling::v2::{
+    CrossVersionObjectReference, HorizontalPodAutoscaler, HorizontalPodAutoscalerSpec, MetricSpec,
+    MetricTarget, ResourceMetricSource,
+};
+use kube::api::{DeleteParams, ObjectMeta, Patch, PropagationPolicy};
+use kube::{Api, Client, Error};
+use std::collections::BTreeMap;
+use tracing::{Level, event, instrument};
+
+use crate::{ApplyOptions, ignore_not_found};
+
+/// Scales the `apps/v1` workload `target` (kind, name), e.g.
+/// `("Deployment", "ipfs-gateway")`, between `min` and `max` replicas to keep
+/// average CPU utilization around `cpu_target_pct` percent of the requests
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(client))]
+pub async fn deploy(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    target: (&str, &str),
+    min: i32,
+    max: i32,
+    cpu_target_pct: i32,
+    labels: BTreeMap<String, String>,
+    apply_options: &ApplyOptions,
+) -> Result<HorizontalPodAutoscaler, crate::Error> {
+    if min > max {
+        return Err(crate::Error::InvalidHpaRange(min, max));
+    }
+
+    let object: HorizontalPodAutoscaler = HorizontalPodAutoscaler {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            namespace: Some(namespace.to_owned()),
+            labels: Some(labels.clone()),
+            ..ObjectMeta::default()
+        },
+        spec: Some(HorizontalPodAutoscalerSpec {
+            scale_target_ref: CrossVersionObjectReference {
+                api_version: Some("apps/v1".to_owned()),
+                kind: target.0.to_owned(),
+                name: target.1.to_owned(),
+            },
+            min_replicas: Some(min),
+            max_replicas: max,
+            metrics: Some(vec![MetricSpec {
+                type_: "Resource".to_owned(),
+                resource: Some(ResourceMetricSource {
+                    name: "cpu".to_owned(),
+                    target: MetricTarget {
+                        type_: "Utilization".to_owned(),
+                        average_utilization: Some(cpu_target_pct),
+                        ..MetricTarget::default()
+                    },
+                }),
+                ..MetricSpec::default()
+            }]),
+            ..HorizontalPodAutoscalerSpec::default()
+        }),
+        ..HorizontalPodAutoscaler::default()
+    };
+
+    event!(
+        Level::INFO,
+        name,
+        namespace,
+        "Creating HorizontalPodAutoscaler"
+    );
+
+    let api: Api<HorizontalPodAutoscaler> = Api::namespaced(client, namespace);
+    let params = apply_options.patch_params();
+    Ok(api.patch(name, &params, &Patch::Apply(&object)).await?)
+}
+
+#[instrument(skip(client))]
+pub async fn delete(
+    client: Client,
+    name: String,
+    namespace: String,
+    propagation_policy: Option<PropagationPolicy>,
+) -> Result<(), Error> {
+    event!(
+        Level::INFO,
+        name,
+        namespace,
+        "Deleting HorizontalPodAutoscaler"
+    );
+
+    let api: Api<HorizontalPodAutoscaler> = Api::namespaced(client, namespace.as_str());
+    let dp = DeleteParams {
+        propagation_policy,
+        ..DeleteParams::default()
+    };
+    // If the resource doesn't exist, we can ignore the error
+    ignore_not_found(api.delete(name.as_str(), &dp).await)?;
+    Ok(())
+}
@@ -2,11 +2,14 @@ use kcr_gateway_networking_k8s_io::v1::httproutes::{
     HTTPRoute, HttpRouteParentRefs, HttpRouteRules, HttpRouteRulesBackendRefs,
     HttpRouteRulesMatches, HttpRouteRulesMatchesPath, HttpRouteRulesMatchesPathType, HttpRouteSpec,
 };
-use kube::api::{DeleteParams, ObjectMeta, Patch, PatchParams};
+use kube::api::{DeleteParams, ObjectMeta, Patch, PropagationPolicy};
 use kube::{Api, Client, Error};
 use std::collections::BTreeMap;
 use tracing::{Level, event, instrument};
 
+use crate::{ApplyOptions, ignore_not_found};
+
+#[allow(clippy::too_many_arguments)]
 #[instrument(skip(client))]
 pub async fn deploy(
     client: Client,
@@ -16,6 +19,7 @@ pub async fn deploy(
     gateway_class_name: &str,
     certificate_secret_name: &str,
     labels: BTreeMap<String, String>,
+    apply_options: &ApplyOptions,
 ) -> Result<HTTPRoute, Error> {
     let object: HTTPRoute = HTTPRoute {
         metadata: ObjectMeta {
@@ -55,30 +59,27 @@ pub async fn deploy(
 
     // Create the pvc defined above
     let service_api: Api<HTTPRoute> = Api::namespaced(client, namespace);
-    let params = PatchParams::apply(name);
+    let params = apply_options.patch_params();
     service_api
         .patch(name, &params, &Patch::Apply(&object))
         .await
 }
 
 #[instrument(skip(client))]
-pub async fn delete(client: Client, name: String, namespace: String) -> Result<(), Error> {
+pub async fn delete(
+    client: Client,
+    name: String,
+    namespace: String,
+    propagation_policy: Option<PropagationPolicy>,
+) -> Result<(), Error> {
     event!(Level::INFO, name, namespace, "Deleting HTTPRoute");
 
     let api: Api<HTTPRoute> = Api::namespaced(client, namespace.as_str());
-    match api.delete(name.as_str(), &DeleteParams::default()).await {
-        Ok(_) => Ok(()),
-        Err(e) => {
-            match e {
-                // If the resource doesn't exist, we can ignore the error
-                Error::Api(er) => {
-                    if er.reason == "NotFound" {
-                        return Ok(());
-                    };
-                    Err(Error::Api(er))
-                }
-                _ => Err(e),
-            }
-        }
-    }
+    let dp = DeleteParams {
+        propagation_policy,
+        ..DeleteParams::default()
+    };
+    // If the resource doesn't exist, we can ignore the error
+    ignore_not_found(api.delete(name.as_str(), &dp).await)?;
+    Ok(())
 }
@@ -0,0 +1,91 @@
+use k8s_openapi::api::networking::v1::{
+    HTTPIngressPath, HTTPIngressRuleValue, Ingress, IngressBackend, IngressRule,
+    IngressServiceBackend, IngressSpec, ServiceBackendPort,
+};
+use kube::api::{DeleteParams, ObjectMeta, Patch, PropagationPolicy};
+use kube::{Api, Client, Error};
+use std::collections::BTreeMap;
+use tracing::{Level, event, instrument};
+
+use crate::{ApplyOptions, ignore_not_found};
+
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(client))]
+pub async fn deploy(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    host: &str,
+    path: &str,
+    service_name: &str,
+    service_port: i32,
+    labels: BTreeMap<String, String>,
+    apply_options: &ApplyOptions,
+) -> Result<Ingress, Error> {
+    let object: Ingress = Ingress {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            namespace: Some(namespace.to_owned()),
+            labels: Some(labels.clone()),
+            ..ObjectMeta::default()
+        },
+        spec: Some(IngressSpec {
+            rules: Some(vec![IngressRule {
+                host: Some(host.to_owned()),
+                http: Some(HTTPIngressRuleValue {
+                    paths: vec![HTTPIngressPath {
+                        path: Some(path.to_owned()),
+                        path_type: "Prefix".to_owned(),
+                        backend: IngressBackend {
+                            service: Some(IngressServiceBackend {
+                                name: service_name.to_owned(),
+                                port: Some(ServiceBackendPort {
+                                    number: Some(service_port),
+                                    ..ServiceBackendPort::default()
+                                }),
+                            }),
+                            ..IngressBackend::default()
+                        },
+                    }],
+                }),
+            }]),
+            ..IngressSpec::default()
+        }),
+        ..Ingress::default()
+    };
+
+    event!(Level::INFO, name, namespace, "Creating Ingress");
+
+    let api: Api<Ingress> = Api::namespaced(client, namespace);
+    let params = apply_options.patch_params();
+    api.patch(name, &params, &Patch::Apply(&object)).await
+}
+
+#[instrument(skip(client))]
+pub async fn get_opt(
+    client: Client,
+    name: &str,
+    namespace: &str,
+) -> Result<Option<Ingress>, crate::Error> {
+    let api: Api<Ingress> = Api::namespaced(client, namespace);
+    Ok(api.get_opt(name).await?)
+}
+
+#[instrument(skip(client))]
+pub async fn delete(
+    client: Client,
+    name: String,
+    namespace: String,
+    propagation_policy: Option<PropagationPolicy>,
+) -> Result<(), Error> {
+    event!(Level::INFO, name, namespace, "Deleting Ingress");
+
+    let api: Api<Ingress> = Api::namespaced(client, namespace.as_str());
+    let dp = DeleteParams {
+        propagation_policy,
+        ..DeleteParams::default()
+    };
+    // If the resource doesn't exist, we can ignore the error
+    ignore_not_found(api.delete(name.as_str(), &dp).await)?;
+    Ok(())
+}
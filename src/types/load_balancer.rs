@@ -1,63 +1,221 @@
-use k8s_openapi::api::core::v1::Service;
-use kube::{
-    Api, Client, Error, ResourceExt,
-    api::ListParams,
-    core::{Status, response::StatusSummary},
-};
+use k8s_openapi::api::core::v1::{ConfigMap, Event, LoadBalancerIngress, Service};
+use kube::api::ListParams;
+use kube::{Api, Client, ResourceExt};
 use kube_runtime::wait::{Condition, await_condition};
-use std::{collections::BTreeMap, time::Duration};
-use tokio::task::JoinSet;
-use tracing::{error, instrument};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Display,
+    future::pending,
+    net::IpAddr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::Semaphore;
+use tokio::task::{Id, JoinError, JoinSet};
+use tokio::time::{Instant, sleep, sleep_until};
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, Span, error, info_span, instrument};
 
 use crate::{
-    labels, selector_labels,
-    types::service::{self, Port, ServiceType},
+    ApplyOptions, external_address_name,
+    multiaddr::{build_multiaddr, parse_multiaddr},
+    types::configmap::{self, ConfigMapOptions},
+    types::service::{self, IpFamily, Port, Protocol, ServiceOptions, ServiceType},
 };
 
+/// How long to wait for a LoadBalancer address when the caller doesn't specify
+pub const DEFAULT_WAIT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Bound on per-replica API calls in flight at once, so scaling to hundreds
+/// of LoadBalancers doesn't get the operator rate limited
+pub const DEFAULT_MAX_CONCURRENCY: usize = 16;
+
+/// Service name for each replica's LoadBalancer, see `service_name`
+pub const DEFAULT_NAME_TEMPLATE: &str = "{name}-{idx}";
+
+/// Renders a LoadBalancer Service name from a template such as
+/// `{name}-quic-{idx}`, so an instance can run several groups of per-replica
+/// LoadBalancers side by side. The selected pod is always `{name}-{idx}`, and
+/// the `get_*external*` results stay keyed by pod name.
+pub fn service_name(template: &str, name: &str, idx: usize) -> String {
+    template
+        .replace("{name}", name)
+        .replace("{idx}", &idx.to_string())
+}
+
+fn validate_name_template(template: &str) -> Result<(), crate::Error> {
+    if !template.contains("{idx}") {
+        return Err(crate::Error::InvalidNameTemplate(template.to_owned()));
+    }
+    Ok(())
+}
+
+fn replica_count(replicas: i32) -> Result<usize, crate::Error> {
+    usize::try_from(replicas).map_err(|_| crate::Error::NegativeReplicas(replicas))
+}
+
+/// Replica index of a Service named by `template`, `None` for Services of
+/// another group
+fn template_index(template: &str, name: &str, svc_name: &str) -> Option<usize> {
+    let (prefix, suffix) = template.split_once("{idx}")?;
+    let prefix = prefix.replace("{name}", name);
+    let suffix = suffix.replace("{name}", name);
+    svc_name
+        .strip_prefix(prefix.as_str())?
+        .strip_suffix(suffix.as_str())?
+        .parse()
+        .ok()
+}
+
+/// Controls how the `get_*external*` functions wait for addresses
+#[derive(Debug, Default, Clone)]
+pub struct WaitOptions {
+    /// Per-replica timeout; `DEFAULT_WAIT_TIMEOUT` when unset
+    pub timeout: Option<Duration>,
+    /// Budget for the whole call, across all replicas. Once it is spent the
+    /// remaining waits are aborted with `Error::IPTimeout`.
+    pub deadline: Option<Duration>,
+    /// Aborts every outstanding wait with `Error::Cancelled` once cancelled,
+    /// e.g. when the controller shuts down
+    pub cancel: Option<CancellationToken>,
+    /// How many replicas to watch at once; `DEFAULT_MAX_CONCURRENCY` when unset
+    pub max_concurrency: Option<usize>,
+}
+
+impl WaitOptions {
+    fn max_concurrency(&self) -> usize {
+        self.max_concurrency
+            .unwrap_or(DEFAULT_MAX_CONCURRENCY)
+            .max(1)
+    }
+}
+
+/// Desired lifecycle step for a set of LoadBalancers, see `reconcile`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ActionType {
+    Create,
+    Update,
+    Delete,
+}
+
+/// Single entry point for the whole lifecycle. `Create` and `Update` both go
+/// through `deploy`, which adds or removes LoadBalancers to match `replicas`;
+/// `Delete` removes them all, whatever their name template, without waiting
+/// for them to be gone.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(client))]
+pub async fn reconcile(
+    action: ActionType,
+    client: Client,
+    name: String,
+    namespace: String,
+    name_template: &str,
+    replicas: i32,
+    ports: Vec<Port>,
+    labels: (BTreeMap<String, String>, BTreeMap<String, String>),
+    options: ServiceOptions,
+    apply_options: &ApplyOptions,
+) -> Result<(), crate::Error> {
+    match action {
+        ActionType::Create | ActionType::Update => {
+            deploy(
+                client,
+                name,
+                namespace,
+                name_template,
+                replicas,
+                ports,
+                labels,
+                options,
+                apply_options,
+            )
+            .await
+        }
+        ActionType::Delete => delete(client, name, namespace, None).await,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 #[instrument(skip(client))]
 pub async fn deploy(
     client: Client,
     name: String,
     namespace: String,
-    kind: String,
+    name_template: &str,
     replicas: i32,
     ports: Vec<Port>,
     labels: (BTreeMap<String, String>, BTreeMap<String, String>),
+    options: ServiceOptions,
+    apply_options: &ApplyOptions,
 ) -> Result<(), crate::Error> {
-    let service_api: Api<Service> = Api::namespaced(client.clone(), namespace.as_str());
-    let lp = ListParams::default()
-        .match_any()
-        .timeout(300)
-        .labels(format!("app.kubernetes.io/instance={name}").as_str())
-        .labels("app.kubernetes.io/name=ipfs-storage-cluster")
-        .fields("spec.type=LoadBalancer");
-    let existing_load_balancers = service_api.list(&lp).await?;
-    let lb_count = existing_load_balancers.items.len();
-
-    if lb_count > replicas as usize {
+    // Fail fast, before any replica's Service is touched
+    validate_name_template(name_template)?;
+    service::validate_ports(&ports)?;
+    service::validate_options(&ServiceType::LoadBalancer, &options, &ports)?;
+    let replicas = replica_count(replicas)?;
+
+    // Only this group's LoadBalancers; other templates share the instance label
+    let existing: BTreeSet<usize> = service::list_load_balancers(client.clone(), &name, &namespace)
+        .await?
+        .iter()
+        .filter_map(|svc| template_index(name_template, &name, &svc.name_any()))
+        .collect();
+
+    if existing.range(replicas..).next().is_some() {
         // Handle excess load balancers
         let mut set = JoinSet::new();
-        for idx in (replicas as usize)..lb_count {
+        let semaphore = Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENCY));
+        for &idx in existing.range(replicas..) {
             let cli = client.clone();
-            let n = name.to_owned();
             let ns = namespace.to_owned();
 
-            set.spawn(service::delete(cli, format!("{n}-{idx}"), ns.clone()));
+            let svc_name = service_name(name_template, &name, idx);
+            let span = replica_span(idx, &svc_name);
+            let delete = service::delete(cli, svc_name, ns.clone(), None);
+            set.spawn(limited(semaphore.clone(), delete).instrument(span));
         }
 
+        // Drain every delete so a single failure doesn't abort the rest, then
+        // surface the first error encountered
+        let mut first_err: Option<crate::Error> = None;
         while let Some(res) = set.join_next().await {
-            res??;
+            let err = match res {
+                Ok(Ok(_)) => continue,
+                Ok(Err(e)) => crate::Error::from(e),
+                Err(e) => crate::Error::from(e),
+            };
+            error!(error = err.to_string());
+            first_err.get_or_insert(err);
         }
-    } else if lb_count < replicas as usize {
+
+        if let Some(err) = first_err {
+            return Err(err);
+        }
+    }
+
+    let missing: Vec<usize> = (0..replicas)
+        .filter(|idx| !existing.contains(idx))
+        .collect();
+    if !missing.is_empty() {
+        // A replica whose Service is gone has no live address to pin, so
+        // fall back to the one it last published
+        let previous_ips = match options.preserve_ip {
+            Some(_) => published_ips(client.clone(), &name, &namespace).await?,
+            None => BTreeMap::new(),
+        };
+
         // Handle insufficient load balancers
         _create(
             client,
             name,
             namespace,
-            kind,
+            name_template,
             ports,
-            lb_count,
-            replicas as usize,
+            labels,
+            options,
+            apply_options,
+            missing,
+            previous_ips,
         )
         .await?;
     }
@@ -70,97 +228,582 @@ pub async fn get_external_ips(
     client: Client,
     name: String,
     namespace: String,
+    name_template: &str,
     port: Port,
     replicas: i32,
+    options: WaitOptions,
 ) -> Result<BTreeMap<String, String>, crate::Error> {
-    let mut external_addrs: BTreeMap<String, String> = BTreeMap::new();
+    wait_each(
+        client,
+        name,
+        namespace,
+        name_template,
+        replicas,
+        options,
+        wait,
+    )
+    .await
+}
+
+/// Like `get_external_ips`, but returns every address assigned to each
+/// replica's LoadBalancer rather than only the first.
+#[instrument(skip(client))]
+pub async fn get_all_external_ips(
+    client: Client,
+    name: String,
+    namespace: String,
+    name_template: &str,
+    port: Port,
+    replicas: i32,
+    options: WaitOptions,
+) -> Result<BTreeMap<String, Vec<String>>, crate::Error> {
+    wait_each(
+        client,
+        name,
+        namespace,
+        name_template,
+        replicas,
+        options,
+        wait_all,
+    )
+    .await
+}
+
+/// Reports the address each replica's LoadBalancer has right now, `None` if
+/// it has none yet or the Service doesn't exist. Unlike `get_external_ips`
+/// this never waits, so it suits status reporting.
+#[instrument(skip(client))]
+pub async fn check_external_ips(
+    client: Client,
+    name: String,
+    namespace: String,
+    name_template: &str,
+    replicas: i32,
+) -> Result<BTreeMap<String, Option<String>>, crate::Error> {
+    validate_name_template(name_template)?;
 
     let mut set = JoinSet::new();
-    for idx in 0..replicas {
+    let semaphore = Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENCY));
+    for idx in 0..replica_count(replicas)? {
         let cli = client.clone();
-        let n = name.to_owned();
-        let ns = namespace.to_owned();
+        let pod_name = format!("{name}-{idx}");
+        let svc_name = service_name(name_template, &name, idx);
+        let ns = namespace.clone();
 
-        set.spawn(async move {
-            wait(cli, format!("{n}-{idx}"), ns)
-                .await
-                .map(|ip_address| (format!("{n}-{idx}"), ip_address))
-        });
+        let span = replica_span(idx, &svc_name);
+        set.spawn(
+            limited(semaphore.clone(), async move {
+                let svc = service::get_opt(cli, &svc_name, &ns).await?;
+                let address = svc
+                    .and_then(|svc| svc.status)
+                    .and_then(|status| status.load_balancer)
+                    .and_then(|lb| lb.ingress)
+                    .and_then(|ingress| ingress.first().and_then(ingress_address));
+                Ok::<_, crate::Error>((pod_name, address))
+            })
+            .instrument(span),
+        );
     }
 
+    let mut external_addrs = BTreeMap::new();
     while let Some(res) = set.join_next().await {
-        let (pod_name, ip_address) = res??;
-        external_addrs.insert(pod_name, ip_address);
+        let (pod_name, address) = res??;
+        external_addrs.insert(pod_name, address);
     }
 
     Ok(external_addrs)
 }
 
+/// Addresses found, plus the failure for each replica without one
+pub type PartialExternalIps = (BTreeMap<String, String>, Vec<(String, crate::Error)>);
+
+/// Like `get_external_ips`, but keeps going when individual replicas fail.
+/// Returns the addresses found plus the error for each replica that had none,
+/// so the caller can publish what it has and requeue for the rest.
 #[instrument(skip(client))]
-pub async fn delete(client: Client, name: String, namespace: String) -> Result<(), Error> {
-    let service_api: Api<Service> = Api::namespaced(client.clone(), namespace.as_str());
-    let lp = ListParams::default()
-        .match_any()
-        .timeout(300)
-        .labels(format!("app.kubernetes.io/instance={name}").as_str())
-        .fields("spec.type=LoadBalancer");
-    let existing_load_balancers = service_api.list(&lp).await?;
+pub async fn get_external_ips_partial(
+    client: Client,
+    name: String,
+    namespace: String,
+    name_template: &str,
+    replicas: i32,
+    options: WaitOptions,
+) -> Result<PartialExternalIps, crate::Error> {
+    validate_name_template(name_template)?;
+    let timeout = options.timeout.unwrap_or(DEFAULT_WAIT_TIMEOUT);
+    let deadline = options.deadline.map(|budget| Instant::now() + budget);
 
     let mut set = JoinSet::new();
+    let semaphore = Arc::new(Semaphore::new(options.max_concurrency()));
+    let mut pod_names = HashMap::new();
+    for idx in 0..replica_count(replicas)? {
+        let pod_name = format!("{name}-{idx}");
+        let svc_name = service_name(name_template, &name, idx);
+        let fut = wait(client.clone(), svc_name.clone(), namespace.clone(), timeout);
+
+        let span = replica_span(idx, &svc_name);
+        let handle = set.spawn(limited(semaphore.clone(), fut).instrument(span));
+        pod_names.insert(handle.id(), pod_name);
+    }
+
+    let mut external_addrs = BTreeMap::new();
+    let mut failures = Vec::new();
+    loop {
+        let res = match join_next(&mut set, &options.cancel, deadline).await {
+            Ok(Some(res)) => res,
+            Ok(None) => break,
+            // Out of budget: whatever is still outstanding timed out
+            Err(crate::Error::IPTimeout) => {
+                failures.extend(
+                    pod_names
+                        .into_values()
+                        .map(|pod_name| (pod_name, crate::Error::IPTimeout)),
+                );
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+
+        match res {
+            Ok((id, Ok(address))) => {
+                external_addrs.insert(pod_names.remove(&id).unwrap_or_default(), address);
+            }
+            Ok((id, Err(e))) => failures.push((pod_names.remove(&id).unwrap_or_default(), e)),
+            Err(e) => failures.push((pod_names.remove(&e.id()).unwrap_or_default(), e.into())),
+        }
+    }
+
+    Ok((external_addrs, failures))
+}
+
+/// Address from a LoadBalancer's ingress status: an IP where the provider
+/// assigned one, otherwise a DNS name (e.g. an AWS ELB)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ExternalAddress {
+    Ip(IpAddr),
+    Hostname(String),
+}
+
+impl ExternalAddress {
+    pub fn parse(address: &str) -> Self {
+        match address.parse::<IpAddr>() {
+            Ok(ip) => ExternalAddress::Ip(ip),
+            Err(_) => ExternalAddress::Hostname(address.to_owned()),
+        }
+    }
+
+    /// `None` for hostnames
+    pub fn family(&self) -> Option<IpFamily> {
+        match self {
+            ExternalAddress::Ip(IpAddr::V4(_)) => Some(IpFamily::Ipv4),
+            ExternalAddress::Ip(IpAddr::V6(_)) => Some(IpFamily::Ipv6),
+            ExternalAddress::Hostname(_) => None,
+        }
+    }
+}
+
+impl Display for ExternalAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExternalAddress::Ip(ip) => write!(f, "{ip}"),
+            ExternalAddress::Hostname(hostname) => write!(f, "{hostname}"),
+        }
+    }
+}
+
+/// Like `get_external_ips`, but parses each address into an `ExternalAddress`
+#[instrument(skip(client))]
+pub async fn get_external_addresses(
+    client: Client,
+    name: String,
+    namespace: String,
+    name_template: &str,
+    replicas: i32,
+    options: WaitOptions,
+) -> Result<BTreeMap<String, ExternalAddress>, crate::Error> {
+    let addrs = wait_each(
+        client,
+        name,
+        namespace,
+        name_template,
+        replicas,
+        options,
+        wait,
+    )
+    .await?;
+    Ok(addrs
+        .into_iter()
+        .map(|(pod_name, address)| (pod_name, ExternalAddress::parse(&address)))
+        .collect())
+}
+
+/// Like `get_external_ips`, but tags each address with its IP family so
+/// dual-stack callers can tell v4 from v6. Hostnames have no family.
+#[instrument(skip(client))]
+pub async fn get_external_ips_with_family(
+    client: Client,
+    name: String,
+    namespace: String,
+    name_template: &str,
+    replicas: i32,
+    options: WaitOptions,
+) -> Result<BTreeMap<String, (Option<IpFamily>, String)>, crate::Error> {
+    let addrs = wait_each(
+        client,
+        name,
+        namespace,
+        name_template,
+        replicas,
+        options,
+        wait,
+    )
+    .await?;
+    Ok(addrs
+        .into_iter()
+        .map(|(pod_name, address)| (pod_name, (IpFamily::of(&address), address)))
+        .collect())
+}
+
+/// Like `get_external_ips`, but formats each address as a libp2p multiaddr,
+/// e.g. `/ip4/1.2.3.4/tcp/4001`, `/ip6/::1/udp/4001` or `/dns4/lb.example.com/tcp/4001`
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(client))]
+pub async fn get_external_multiaddrs(
+    client: Client,
+    name: String,
+    namespace: String,
+    name_template: &str,
+    replicas: i32,
+    port: i32,
+    protocol: Protocol,
+    options: WaitOptions,
+) -> Result<BTreeMap<String, String>, crate::Error> {
+    let addrs = wait_each(
+        client,
+        name,
+        namespace,
+        name_template,
+        replicas,
+        options,
+        wait,
+    )
+    .await?;
+    addrs
+        .into_iter()
+        .map(|(pod_name, address)| Ok((pod_name, build_multiaddr(&address, port, protocol)?)))
+        .collect()
+}
+
+/// Stores the addresses from `get_external_ips`/`get_external_multiaddrs` in
+/// the `external_address_name(name)` ConfigMap, e.g. with `crate::labels` or
+/// `LabelConfig::labels` for `labels`
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(client))]
+pub async fn publish_external_addresses(
+    client: Client,
+    name: String,
+    namespace: String,
+    addresses: BTreeMap<String, String>,
+    labels: BTreeMap<String, String>,
+    options: ConfigMapOptions,
+    apply_options: &ApplyOptions,
+) -> Result<ConfigMap, crate::Error> {
+    Ok(configmap::deploy(
+        client,
+        &external_address_name(&name),
+        &namespace,
+        addresses,
+        labels,
+        options,
+        apply_options,
+    )
+    .await?)
+}
+
+/// IPs last stored by `publish_external_addresses`, keyed by pod name. Both
+/// plain addresses and multiaddrs are understood; hostnames can't be pinned
+/// and are skipped.
+async fn published_ips(
+    client: Client,
+    name: &str,
+    namespace: &str,
+) -> Result<BTreeMap<String, IpAddr>, crate::Error> {
+    let data = match configmap::get_data_opt(client, &external_address_name(name), namespace).await
+    {
+        Ok(data) => data.unwrap_or_default(),
+        Err(crate::Error::ConfigMapMissingData { .. }) => BTreeMap::new(),
+        Err(e) => return Err(e),
+    };
+
+    Ok(data
+        .into_iter()
+        .filter_map(|(pod_name, address)| {
+            let ip = match address.parse::<IpAddr>() {
+                Ok(ip) => ip,
+                Err(_) => parse_multiaddr(&address).ok()?.0.parse().ok()?,
+            };
+            Some((pod_name, ip))
+        })
+        .collect())
+}
+
+async fn wait_each<T, F, Fut>(
+    client: Client,
+    name: String,
+    namespace: String,
+    name_template: &str,
+    replicas: i32,
+    options: WaitOptions,
+    waiter: F,
+) -> Result<BTreeMap<String, T>, crate::Error>
+where
+    T: Send + 'static,
+    F: Fn(Client, String, String, Duration) -> Fut,
+    Fut: Future<Output = Result<T, crate::Error>> + Send + 'static,
+{
+    validate_name_template(name_template)?;
+    let mut external_addrs: BTreeMap<String, T> = BTreeMap::new();
+    let timeout = options.timeout.unwrap_or(DEFAULT_WAIT_TIMEOUT);
+    let deadline = options.deadline.map(|budget| Instant::now() + budget);
+
+    let mut set = JoinSet::new();
+    let semaphore = Arc::new(Semaphore::new(options.max_concurrency()));
+    for idx in 0..replica_count(replicas)? {
+        let pod_name = format!("{name}-{idx}");
+        let svc_name = service_name(name_template, &name, idx);
+        let fut = waiter(client.clone(), svc_name.clone(), namespace.clone(), timeout);
+
+        let span = replica_span(idx, &svc_name);
+        let fut = limited(semaphore.clone(), fut);
+        set.spawn(async move { fut.await.map(|address| (pod_name, address)) }.instrument(span));
+    }
+
+    while let Some(res) = join_next(&mut set, &options.cancel, deadline).await? {
+        let (_, res) = res?;
+        let (pod_name, address) = res?;
+        external_addrs.insert(pod_name, address);
+    }
+
+    Ok(external_addrs)
+}
+
+/// `JoinSet::join_next_with_id` that aborts the whole set with
+/// `Error::Cancelled` once `cancel` fires, or `Error::IPTimeout` once
+/// `deadline` passes
+async fn join_next<T: 'static>(
+    set: &mut JoinSet<T>,
+    cancel: &Option<CancellationToken>,
+    deadline: Option<Instant>,
+) -> Result<Option<Result<(Id, T), JoinError>>, crate::Error> {
+    let cancelled = async {
+        match cancel {
+            Some(token) => token.cancelled().await,
+            None => pending().await,
+        }
+    };
+    let expired = async {
+        match deadline {
+            Some(deadline) => sleep_until(deadline).await,
+            None => pending().await,
+        }
+    };
+
+    tokio::select! {
+        _ = cancelled => {
+            set.abort_all();
+            Err(crate::Error::Cancelled)
+        }
+        _ = expired => {
+            set.abort_all();
+            Err(crate::Error::IPTimeout)
+        }
+        next = set.join_next_with_id() => Ok(next),
+    }
+}
+
+/// Deletes every LoadBalancer Service of the instance. With `wait_timeout`
+/// set, also waits for each Service to be gone so that a following
+/// `deploy` can't collide with one that is still terminating.
+#[instrument(skip(client))]
+pub async fn delete(
+    client: Client,
+    name: String,
+    namespace: String,
+    wait_timeout: Option<Duration>,
+) -> Result<(), crate::Error> {
+    let existing_load_balancers =
+        service::list_load_balancers(client.clone(), &name, &namespace).await?;
+
+    let mut set = JoinSet::new();
+    let semaphore = Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENCY));
     for lb in existing_load_balancers {
         let cli = client.clone();
         let ns = namespace.to_owned();
 
-        set.spawn(service::delete(cli, lb.name_any(), ns.clone()));
+        set.spawn(
+            limited(semaphore.clone(), async move {
+                let svc_name = lb.name_any();
+                service::delete(cli.clone(), svc_name.clone(), ns.clone(), None).await?;
+                if let Some(timeout) = wait_timeout {
+                    let api: Api<Service> = Api::namespaced(cli, ns.as_str());
+                    crate::wait_for_deletion(api, &svc_name, timeout).await?;
+                }
+                Ok::<(), crate::Error>(())
+            })
+            .in_current_span(),
+        );
     }
 
     while let Some(res) = set.join_next().await {
-        match res {
-            Ok(_) => (),
-            Err(err) => {
-                return Err(Error::Api(
-                    Status {
-                        metadata: Default::default(),
-                        details: None,
-                        status: Some(StatusSummary::Failure),
-                        message: err.to_string(),
-                        reason: "Failed to join set while deleting load balancers".to_string(),
-                        code: 418,
-                    }
-                    .boxed(),
-                ));
-            }
-        }
+        res??;
     }
 
     Ok(())
 }
 
+/// Waits for the Service to be assigned an external address. The returned
+/// string is the ingress IP when one is set, otherwise the ingress hostname
+/// (e.g. on AWS, where load balancers are addressed by DNS name). Returns
+/// `Error::LoadBalancerFailed` as soon as the provider reports it cannot
+/// provision one, rather than running out the timeout.
 #[instrument(skip(client))]
 pub async fn wait(
     client: Client,
     name: String,
     namespace: String,
+    timeout: Duration,
 ) -> std::result::Result<String, crate::Error> {
-    let service_api: Api<Service> = Api::namespaced(client, namespace.as_str());
+    let ingress = wait_for_ingress(client, name, namespace, timeout).await?;
+    match ingress.first().and_then(ingress_address) {
+        Some(address) => Ok(address),
+        None => Err(crate::Error::IngressListEmpty),
+    }
+}
 
-    let exists = await_condition(service_api, name.as_str(), external_ip_exists());
-    let out = tokio::time::timeout(Duration::from_secs(300), exists).await?;
+/// Waits for the Service to be assigned an external address and returns every
+/// populated ingress IP or hostname, e.g. both families on a dual-stack LB.
+#[instrument(skip(client))]
+pub async fn wait_all(
+    client: Client,
+    name: String,
+    namespace: String,
+    timeout: Duration,
+) -> std::result::Result<Vec<String>, crate::Error> {
+    let ingress = wait_for_ingress(client, name, namespace, timeout).await?;
+    let addresses = ingress_addresses(&ingress);
+    if addresses.is_empty() {
+        return Err(crate::Error::IngressListEmpty);
+    }
+    Ok(addresses)
+}
+
+/// Event and condition reasons with which providers report that a
+/// LoadBalancer could not be provisioned, e.g. when the address quota is
+/// exhausted. The service controller emits `SyncLoadBalancerFailed` on every
+/// failed attempt and retries with backoff, so none of these is terminal by
+/// itself: a condition with one of them only counts while its status is
+/// `False`, and an Event only once it has recurred `FAILURE_EVENT_COUNT` times.
+const FAILURE_REASONS: &[&str] = &[
+    "SyncLoadBalancerFailed",
+    "CreatingLoadBalancerFailed",
+    "AllocationFailed",
+];
+
+/// How many times a Warning Event with one of `FAILURE_REASONS` must have
+/// been reported before `wait` gives up, so that a single transient failure
+/// the controller later recovers from is outlasted
+const FAILURE_EVENT_COUNT: i32 = 3;
+
+/// How often `wait` checks for a reported provisioning failure
+const FAILURE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+async fn wait_for_ingress(
+    client: Client,
+    name: String,
+    namespace: String,
+    timeout: Duration,
+) -> std::result::Result<Vec<LoadBalancerIngress>, crate::Error> {
+    let service_api: Api<Service> = Api::namespaced(client.clone(), namespace.as_str());
+
+    let exists = await_condition(service_api.clone(), name.as_str(), external_ip_exists());
+    let failed = provisioning_failure(client, service_api, &name, &namespace);
+    let out = tokio::time::timeout(timeout, async {
+        tokio::select! {
+            biased;
+            out = exists => Ok(out),
+            reason = failed => Err(crate::Error::LoadBalancerFailed(reason)),
+        }
+    })
+    .await??;
     match out {
         Ok(res) => match res.unwrap().status.unwrap().load_balancer.unwrap().ingress {
-            Some(ingress) => {
-                if !ingress.is_empty() {
-                    return Ok(ingress[0].clone().ip.unwrap());
-                } else {
-                    Err(crate::Error::IngressListEmpty)
-                }
-            }
+            Some(ingress) => Ok(ingress),
             None => Err(crate::Error::IngressListMissing),
         },
         Err(e) => Err(crate::Error::WaitError { source: e }),
     }
 }
 
+/// Resolves with the reason once the Service reports a failed condition or
+/// has a persistent Warning Event with one of `FAILURE_REASONS`, and never
+/// while it has no failure or already has an address. Events are matched by the
+/// Service's UID, so those left by an earlier Service of the same name are
+/// ignored. API errors are retried on the next poll.
+async fn provisioning_failure(
+    client: Client,
+    service_api: Api<Service>,
+    name: &str,
+    namespace: &str,
+) -> String {
+    let event_api: Api<Event> = Api::namespaced(client, namespace);
+    loop {
+        if let Ok(Some(svc)) = service_api.get_opt(name).await
+            && !external_ip_exists().matches_object(Some(&svc))
+        {
+            let status = svc.status.unwrap_or_default();
+            let condition = status
+                .conditions
+                .unwrap_or_default()
+                .into_iter()
+                .find(|c| c.status == "False" && FAILURE_REASONS.contains(&c.reason.as_str()));
+            if let Some(c) = condition {
+                return format!("{}: {}", c.reason, c.message);
+            }
+
+            let lp = ListParams::default().fields(&format!(
+                "involvedObject.uid={},type=Warning",
+                svc.metadata.uid.unwrap_or_default()
+            ));
+            if let Ok(events) = event_api.list(&lp).await
+                && let Some(event) = events.items.into_iter().find(persistent_failure)
+            {
+                return format!(
+                    "{}: {}",
+                    event.reason.unwrap_or_default(),
+                    event.message.unwrap_or_default()
+                );
+            }
+        }
+        sleep(FAILURE_POLL_INTERVAL).await;
+    }
+}
+
+/// Whether `event` reports one of `FAILURE_REASONS` that has recurred at
+/// least `FAILURE_EVENT_COUNT` times, counting an event series if present
+fn persistent_failure(event: &Event) -> bool {
+    let count = event
+        .series
+        .as_ref()
+        .and_then(|series| series.count)
+        .or(event.count)
+        .unwrap_or(1);
+    event
+        .reason
+        .as_deref()
+        .is_some_and(|reason| FAILURE_REASONS.contains(&reason))
+        && count >= FAILURE_EVENT_COUNT
+}
+
 #[instrument]
 fn external_ip_exists() -> impl Condition<Service> {
     move |obj: Option<&Service>| {
@@ -168,7 +811,8 @@ fn external_ip_exists() -> impl Condition<Service> {
             && let Some(status) = &svc.status
             && let Some(lb) = &status.load_balancer
             && let Some(ingress) = &lb.ingress
-            && let Some(_ip) = &ingress[0].ip
+            && let Some(first) = ingress.first()
+            && ingress_address(first).is_some()
         {
             return true;
         }
@@ -176,45 +820,521 @@ fn external_ip_exists() -> impl Condition<Service> {
     }
 }
 
+/// Runs `fut` once a permit is free. A per-replica timeout inside `fut` only
+/// starts counting then, since futures don't run until polled.
+async fn limited<F: Future>(semaphore: Arc<Semaphore>, fut: F) -> F::Output {
+    let _permit = semaphore.acquire().await;
+    fut.await
+}
+
+/// Span for work on one replica's LoadBalancer, so logs can be filtered by
+/// replica index. Created inside the caller's span, which becomes its parent.
+fn replica_span(idx: usize, service: &str) -> Span {
+    info_span!("replica", idx, service)
+}
+
+fn ingress_address(ingress: &LoadBalancerIngress) -> Option<String> {
+    ingress.ip.clone().or_else(|| ingress.hostname.clone())
+}
+
+fn ingress_addresses(ingress: &[LoadBalancerIngress]) -> Vec<String> {
+    ingress.iter().filter_map(ingress_address).collect()
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn _create(
     client: Client,
     name: String,
     namespace: String,
-    kind: String,
+    name_template: &str,
     ports: Vec<Port>,
-    lower: usize,
-    upper: usize,
+    labels: (BTreeMap<String, String>, BTreeMap<String, String>),
+    options: ServiceOptions,
+    apply_options: &ApplyOptions,
+    indices: Vec<usize>,
+    previous_ips: BTreeMap<String, IpAddr>,
 ) -> Result<(), crate::Error> {
     let mut set = JoinSet::new();
+    let semaphore = Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENCY));
 
-    for idx in lower..upper {
+    for idx in indices {
         let pod_name = format!("{name}-{idx}");
-        let mut sl = selector_labels(name.clone(), kind.clone().to_string());
+        let mut sl = labels.1.clone();
         sl.insert(
             "statefulset.kubernetes.io/pod-name".to_owned(),
             pod_name.clone(),
         );
+        // `list_load_balancers` finds the replicas' Services by this label
+        let mut svc_labels = labels.0.clone();
+        svc_labels
+            .entry("app.kubernetes.io/instance".to_owned())
+            .or_insert_with(|| name.clone());
 
         let cli = client.clone();
-        let n = name.to_owned();
         let ns = namespace.to_owned();
 
-        set.spawn(service::deploy(
-            cli,
-            format!("{n}-{idx}"),
-            ns,
-            ServiceType::LoadBalancer,
-            ports.clone(),
-            (labels(name.clone(), kind.clone().to_string()), sl),
+        let svc_labels = (svc_labels, sl);
+        let svc_ports = ports.clone();
+        let mut svc_options = options.clone();
+        if let Some(ip) = previous_ips.get(&pod_name) {
+            svc_options.previous_ip = Some(*ip);
+        }
+        let svc_apply_options = apply_options.clone();
+
+        let svc_name = service_name(name_template, &name, idx);
+        let span = replica_span(idx, &svc_name);
+        set.spawn(
+            limited(semaphore.clone(), async move {
+                let res = service::deploy(
+                    cli,
+                    svc_name,
+                    ns,
+                    ServiceType::LoadBalancer,
+                    svc_ports,
+                    svc_labels,
+                    svc_options,
+                    &svc_apply_options,
+                )
+                .await;
+                if let Err(e) = &res {
+                    error!(error = e.to_string(), "Failed to deploy LoadBalancer");
+                }
+                res
+            })
+            .instrument(span),
+        );
+    }
+
+    // Let every deploy finish so one failure doesn't leave the rest half
+    // done, then surface the first error encountered
+    let mut first_err: Option<crate::Error> = None;
+    while let Some(res) = set.join_next().await {
+        let err = match res {
+            Ok(Ok(_)) => continue,
+            Ok(Err(e)) => e,
+            Err(e) => {
+                error!(error = e.to_string());
+                crate::Error::from(e)
+            }
+        };
+        first_err.get_or_insert(err);
+    }
+
+    match first_err {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{Method, StatusCode};
+    use k8s_openapi::api::core::v1::{LoadBalancerStatus, ServiceStatus};
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::testing::mock_client;
+
+    fn with_ingress(ingress: Vec<LoadBalancerIngress>) -> Service {
+        Service {
+            status: Some(ServiceStatus {
+                load_balancer: Some(LoadBalancerStatus {
+                    ingress: Some(ingress),
+                }),
+                ..ServiceStatus::default()
+            }),
+            ..Service::default()
+        }
+    }
+
+    fn service_list(names: &[&str]) -> Value {
+        let items: Vec<Value> = names
+            .iter()
+            .map(|name| {
+                json!({
+                    "apiVersion": "v1",
+                    "kind": "Service",
+                    "metadata": { "name": name, "namespace": "ns" },
+                })
+            })
+            .collect();
+        json!({ "apiVersion": "v1", "kind": "ServiceList", "metadata": {}, "items": items })
+    }
+
+    async fn deploy_one(client: Client, options: ServiceOptions) -> Result<(), crate::Error> {
+        deploy(
+            client,
+            "inst".to_owned(),
+            "ns".to_owned(),
+            DEFAULT_NAME_TEMPLATE,
+            1,
+            vec![Port::tcp("p2p", 4001)],
+            (BTreeMap::new(), BTreeMap::new()),
+            options,
+            &ApplyOptions::default(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn deploy_pins_recreated_replicas_to_published_ips() {
+        let (client, mut server) = mock_client();
+        let options = ServiceOptions {
+            preserve_ip: Some(service::IpPinning::Spec),
+            ..ServiceOptions::default()
+        };
+        let deploy = tokio::spawn(deploy_one(client, options));
+
+        server
+            .expect_get("/api/v1/namespaces/ns/services", service_list(&[]))
+            .await;
+        let published = json!({
+            "apiVersion": "v1",
+            "kind": "ConfigMap",
+            "metadata": { "name": "inst-external-addresses", "namespace": "ns" },
+            "data": { "inst-0": "/ip4/203.0.113.7/tcp/4001" },
+        });
+        server
+            .expect_get(
+                "/api/v1/namespaces/ns/configmaps/inst-external-addresses",
+                published,
+            )
+            .await;
+        server
+            .expect_not_found(Method::GET, "/api/v1/namespaces/ns/services/inst-0")
+            .await;
+        let body = server
+            .expect_patch(
+                "/api/v1/namespaces/ns/services/inst-0",
+                service_list(&["inst-0"])["items"][0].clone(),
+            )
+            .await;
+        assert_eq!(body["spec"]["loadBalancerIP"], "203.0.113.7");
+
+        deploy.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn deploy_labels_replicas_with_the_callers_labels() {
+        let (client, mut server) = mock_client();
+        let deploy = tokio::spawn(async move {
+            let labels = crate::LabelConfig::new("2.1.0", "my-operator")
+                .labels("inst".to_owned(), "node".to_owned())
+                .unwrap();
+            let selector = crate::selector_labels("inst".to_owned(), "node".to_owned()).unwrap();
+            deploy(
+                client,
+                "inst".to_owned(),
+                "ns".to_owned(),
+                DEFAULT_NAME_TEMPLATE,
+                1,
+                vec![Port::tcp("p2p", 4001)],
+                (labels, selector),
+                ServiceOptions::default(),
+                &ApplyOptions::default(),
+            )
+            .await
+        });
+
+        server
+            .expect_get("/api/v1/namespaces/ns/services", service_list(&[]))
+            .await;
+        let body = server
+            .expect_patch(
+                "/api/v1/namespaces/ns/services/inst-0",
+                service_list(&["inst-0"])["items"][0].clone(),
+            )
+            .await;
+        let labels = &body["metadata"]["labels"];
+        assert_eq!(labels["app.kubernetes.io/version"], "2.1.0");
+        assert_eq!(labels["app.kubernetes.io/managed-by"], "my-operator");
+        assert_eq!(labels["app.kubernetes.io/instance"], "inst");
+        assert_eq!(
+            body["spec"]["selector"],
+            json!({
+                "app.kubernetes.io/instance": "inst",
+                "app.kubernetes.io/name": "ipfs-node-cluster",
+                "statefulset.kubernetes.io/pod-name": "inst-0",
+            })
+        );
+
+        deploy.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn negative_replicas_are_rejected_before_any_request() {
+        let (client, _server) = mock_client();
+        let err = check_external_ips(
+            client.clone(),
+            "inst".to_owned(),
+            "ns".to_owned(),
+            DEFAULT_NAME_TEMPLATE,
+            -1,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, crate::Error::NegativeReplicas(-1)));
+
+        let err = get_external_ips_partial(
+            client,
+            "inst".to_owned(),
+            "ns".to_owned(),
+            DEFAULT_NAME_TEMPLATE,
+            -1,
+            WaitOptions::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, crate::Error::NegativeReplicas(-1)));
+    }
+
+    #[tokio::test]
+    async fn deploy_rejects_invalid_options_before_any_request() {
+        let (client, _server) = mock_client();
+        let options = ServiceOptions {
+            load_balancer_source_ranges: vec!["10.0.0.0/33".to_owned()],
+            ..ServiceOptions::default()
+        };
+
+        let err = deploy_one(client, options).await.unwrap_err();
+        assert!(matches!(err, crate::Error::InvalidCidr(range) if range == "10.0.0.0/33"));
+    }
+
+    #[tokio::test]
+    async fn deploy_returns_replica_deploy_errors() {
+        let (client, mut server) = mock_client();
+        let deploy = tokio::spawn(deploy_one(client, ServiceOptions::default()));
+
+        server
+            .expect_get("/api/v1/namespaces/ns/services", service_list(&[]))
+            .await;
+        let status = json!({
+            "kind": "Status",
+            "apiVersion": "v1",
+            "status": "Failure",
+            "message": "quota exceeded",
+            "reason": "Forbidden",
+            "code": 403,
+        });
+        server
+            .expect(
+                Method::PATCH,
+                "/api/v1/namespaces/ns/services/inst-0",
+                StatusCode::FORBIDDEN,
+                status,
+            )
+            .await;
+
+        let err = deploy.await.unwrap().unwrap_err();
+        assert!(matches!(
+            err,
+            crate::Error::KubeError {
+                source: kube::Error::Api(status)
+            } if status.code == 403
         ));
+    }
 
-        while let Some(res) = set.join_next().await {
-            match res {
-                Ok(_) => (),
-                Err(e) => error!(error = e.to_string()),
+    #[test]
+    fn only_recurring_failure_events_are_terminal() {
+        let event = |reason: &str, count: i32| Event {
+            reason: Some(reason.to_owned()),
+            count: Some(count),
+            ..Event::default()
+        };
+        assert!(!persistent_failure(&event("SyncLoadBalancerFailed", 1)));
+        assert!(persistent_failure(&event(
+            "SyncLoadBalancerFailed",
+            FAILURE_EVENT_COUNT
+        )));
+        assert!(!persistent_failure(&event("EnsuringLoadBalancer", 10)));
+    }
+
+    #[tokio::test]
+    async fn deploy_creates_replicas_concurrently() {
+        let (client, mut server) = mock_client();
+        let deploy = tokio::spawn(async move {
+            deploy(
+                client,
+                "inst".to_owned(),
+                "ns".to_owned(),
+                DEFAULT_NAME_TEMPLATE,
+                2,
+                vec![Port::tcp("p2p", 4001)],
+                (BTreeMap::new(), BTreeMap::new()),
+                ServiceOptions::default(),
+                &ApplyOptions::default(),
+            )
+            .await
+        });
+
+        server
+            .expect_get("/api/v1/namespaces/ns/services", service_list(&[]))
+            .await;
+        // Both applies must be in flight before either is answered
+        let in_flight = tokio::time::timeout(Duration::from_secs(5), async {
+            (server.receive().await, server.receive().await)
+        })
+        .await
+        .expect("replicas were created one at a time");
+        let mut paths = BTreeSet::new();
+        for request in [in_flight.0, in_flight.1] {
+            assert_eq!(request.method, Method::PATCH);
+            let name = request.path.rsplit('/').next().unwrap().to_owned();
+            paths.insert(request.path.clone());
+            request.respond(StatusCode::OK, service_list(&[&name])["items"][0].clone());
+        }
+        assert_eq!(
+            paths,
+            BTreeSet::from([
+                "/api/v1/namespaces/ns/services/inst-0".to_owned(),
+                "/api/v1/namespaces/ns/services/inst-1".to_owned(),
+            ])
+        );
+
+        deploy.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn empty_ingress_list_has_no_external_ip() {
+        let svc = with_ingress(vec![]);
+        assert!(!external_ip_exists().matches_object(Some(&svc)));
+
+        let svc = with_ingress(vec![LoadBalancerIngress::default()]);
+        assert!(!external_ip_exists().matches_object(Some(&svc)));
+    }
+
+    #[test]
+    fn hostname_ingress_is_an_external_address() {
+        let ingress = LoadBalancerIngress {
+            hostname: Some("a1b2.elb.us-east-1.amazonaws.com".to_owned()),
+            ..LoadBalancerIngress::default()
+        };
+        assert_eq!(
+            ingress_address(&ingress).as_deref(),
+            Some("a1b2.elb.us-east-1.amazonaws.com")
+        );
+        let svc = with_ingress(vec![ingress]);
+        assert!(external_ip_exists().matches_object(Some(&svc)));
+    }
+
+    #[test]
+    fn every_ingress_address_is_returned() {
+        let ingress = vec![
+            LoadBalancerIngress {
+                ip: Some("203.0.113.7".to_owned()),
+                ..LoadBalancerIngress::default()
+            },
+            LoadBalancerIngress {
+                ip: Some("2001:db8::7".to_owned()),
+                ..LoadBalancerIngress::default()
+            },
+        ];
+        assert_eq!(
+            ingress_addresses(&ingress),
+            vec!["203.0.113.7".to_owned(), "2001:db8::7".to_owned()]
+        );
+    }
+
+    /// Records each span's name and parent, to check spawned work stays
+    /// attached to the span of the caller
+    #[derive(Default)]
+    struct SpanRecorder {
+        spans: std::sync::Mutex<Vec<(&'static tracing::Metadata<'static>, Option<u64>)>>,
+        entered: std::sync::Mutex<Vec<u64>>,
+    }
+
+    impl SpanRecorder {
+        /// Names from the first span called `name` in `target` up to the root
+        fn ancestry(&self, target: &str, name: &str) -> Vec<&'static str> {
+            let spans = self.spans.lock().unwrap();
+            let mut idx = spans
+                .iter()
+                .position(|(meta, _)| meta.target() == target && meta.name() == name);
+            let mut names = Vec::new();
+            while let Some(i) = idx {
+                names.push(spans[i].0.name());
+                idx = spans[i].1.map(|parent| parent as usize - 1);
             }
+            names
         }
     }
 
-    Ok(())
+    impl tracing::Subscriber for SpanRecorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attrs: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let parent = match attrs.parent() {
+                Some(parent) => Some(parent.into_u64()),
+                None if attrs.is_contextual() => self.entered.lock().unwrap().last().copied(),
+                None => None,
+            };
+            let mut spans = self.spans.lock().unwrap();
+            spans.push((attrs.metadata(), parent));
+            tracing::span::Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, _: &tracing::Event<'_>) {}
+
+        fn enter(&self, span: &tracing::span::Id) {
+            self.entered.lock().unwrap().push(span.into_u64());
+        }
+
+        fn exit(&self, span: &tracing::span::Id) {
+            let mut entered = self.entered.lock().unwrap();
+            if let Some(pos) = entered.iter().rposition(|id| *id == span.into_u64()) {
+                entered.remove(pos);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn replica_work_stays_in_the_callers_span() {
+        let recorder = Arc::new(SpanRecorder::default());
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        let (client, mut server) = mock_client();
+        let deploy = tokio::spawn(
+            deploy_one(client, ServiceOptions::default()).instrument(info_span!("reconcile")),
+        );
+        server
+            .expect_get("/api/v1/namespaces/ns/services", service_list(&[]))
+            .await;
+        server
+            .expect_patch(
+                "/api/v1/namespaces/ns/services/inst-0",
+                service_list(&["inst-0"])["items"][0].clone(),
+            )
+            .await;
+        deploy.await.unwrap().unwrap();
+
+        assert_eq!(
+            recorder.ancestry("operator_common::types::service", "deploy"),
+            ["deploy", "replica", "deploy", "reconcile"]
+        );
+    }
+
+    #[test]
+    fn external_addresses_parse_by_form() {
+        let v4 = ExternalAddress::parse("203.0.113.7");
+        assert_eq!(v4, ExternalAddress::Ip("203.0.113.7".parse().unwrap()));
+        assert_eq!(v4.family(), Some(IpFamily::Ipv4));
+
+        let v6 = ExternalAddress::parse("2001:db8::7");
+        assert_eq!(v6, ExternalAddress::Ip("2001:db8::7".parse().unwrap()));
+        assert_eq!(v6.family(), Some(IpFamily::Ipv6));
+
+        let hostname = ExternalAddress::parse("a1b2.elb.us-east-1.amazonaws.com");
+        assert_eq!(
+            hostname,
+            ExternalAddress::Hostname("a1b2.elb.us-east-1.amazonaws.com".to_owned())
+        );
+        assert_eq!(hostname.family(), None);
+        assert_eq!(hostname.to_string(), "a1b2.elb.us-east-1.amazonaws.com");
+    }
 }
@@ -1,8 +1,16 @@
 pub mod configmap;
+pub mod deployment;
 pub mod gateway;
+pub mod hpa;
 pub mod http_route;
+pub mod ingress;
 pub mod load_balancer;
+pub mod namespace;
+pub mod network_policy;
+pub mod pdb;
+pub mod rbac;
 pub mod secret;
 pub mod security_policy;
 pub mod service;
+pub mod service_account;
 pub mod statefulset;
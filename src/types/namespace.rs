@@ -0,0 +1,91 @@
+use k8s_openapi::api::core::v1::Namespace;
+use kube::api::ObjectMeta;
+use kube::{Api, Client, Error};
+use std::collections::BTreeMap;
+use tracing::{Level, event, instrument};
+
+use crate::ApplyOptions;
+
+/// Creates the namespace unless it already exists. An existing namespace is
+/// left untouched, including its labels.
+#[instrument(skip(client))]
+pub async fn ensure(
+    client: Client,
+    name: &str,
+    labels: BTreeMap<String, String>,
+    apply_options: &ApplyOptions,
+) -> Result<(), Error> {
+    let object: Namespace = Namespace {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            labels: Some(labels),
+            ..ObjectMeta::default()
+        },
+        ..Namespace::default()
+    };
+
+    event!(Level::INFO, name, "Creating Namespace");
+
+    let api: Api<Namespace> = Api::all(client);
+    match api.create(&apply_options.post_params(), &object).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            match e {
+                // Someone else got there first, which is what we wanted
+                Error::Api(er) => {
+                    if er.reason == "AlreadyExists" {
+                        return Ok(());
+                    };
+                    Err(Error::Api(er))
+                }
+                _ => Err(e),
+            }
+        }
+    }
+}
+
+#[instrument(skip(client))]
+pub async fn exists(client: Client, name: &str) -> Result<bool, crate::Error> {
+    let api: Api<Namespace> = Api::all(client);
+    Ok(api.get_opt(name).await?.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{Method, StatusCode};
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::mock_client;
+
+    #[tokio::test]
+    async fn ensure_honours_dry_run() {
+        let (client, mut server) = mock_client();
+        let ensure = tokio::spawn(async move {
+            ensure(
+                client,
+                "ipfs",
+                BTreeMap::new(),
+                &ApplyOptions::default().dry_run(),
+            )
+            .await
+        });
+
+        let namespace = json!({
+            "apiVersion": "v1",
+            "kind": "Namespace",
+            "metadata": { "name": "ipfs" },
+        });
+        let (query, body) = server
+            .expect_with_query(
+                Method::POST,
+                "/api/v1/namespaces",
+                StatusCode::CREATED,
+                namespace,
+            )
+            .await;
+        assert!(query.contains("dryRun=All"), "{query}");
+        assert_eq!(body["metadata"]["name"], "ipfs");
+        ensure.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,201 @@
+use k8s_openapi::api::networking::v1::{
+    NetworkPolicy, NetworkPolicyEgressRule, NetworkPolicyIngressRule, NetworkPolicyPeer,
+    NetworkPolicyPort, NetworkPolicySpec,
+};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use kube::api::{DeleteParams, ObjectMeta, Patch, PropagationPolicy};
+use kube::{Api, Client, Error};
+use std::collections::BTreeMap;
+use tracing::{Level, event, instrument};
+
+use crate::types::service::Protocol;
+use crate::{ApplyOptions, ignore_not_found};
+
+/// Traffic allowed in one direction: from (or to) pods matching any of
+/// `peers`, on any of `ports`
+#[derive(Debug, Default, Clone)]
+pub struct PeerRule {
+    /// Pod label selectors in the policy's namespace; empty allows any peer
+    pub peers: Vec<BTreeMap<String, String>>,
+    /// Empty allows every port
+    pub ports: Vec<(i32, Protocol)>,
+}
+
+impl PeerRule {
+    fn peers(&self) -> Option<Vec<NetworkPolicyPeer>> {
+        (!self.peers.is_empty()).then(|| {
+            self.peers
+                .iter()
+                .map(|labels| NetworkPolicyPeer {
+                    pod_selector: Some(LabelSelector {
+                        match_labels: Some(labels.clone()),
+                        ..LabelSelector::default()
+                    }),
+                    ..NetworkPolicyPeer::default()
+                })
+                .collect()
+        })
+    }
+
+    fn ports(&self) -> Option<Vec<NetworkPolicyPort>> {
+        (!self.ports.is_empty()).then(|| {
+            self.ports
+                .iter()
+                .map(|(port, protocol)| NetworkPolicyPort {
+                    port: Some(IntOrString::Int(*port)),
+                    protocol: Some(protocol.to_string()),
+                    ..NetworkPolicyPort::default()
+                })
+                .collect()
+        })
+    }
+}
+
+/// Restricts traffic to and from the pods matching `selector_labels`. Pass
+/// the output of `crate::selector_labels` to cover the managed pods. For each
+/// direction `None` leaves traffic unrestricted and `Some(vec![])` denies it
+/// all. A single ingress rule with no peers and port TCP/4001 only admits
+/// swarm traffic. At least one direction must be restricted: the API server
+/// treats a policy without either as ingress-only and would deny all ingress.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(client))]
+pub async fn deploy(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    selector_labels: BTreeMap<String, String>,
+    ingress: Option<Vec<PeerRule>>,
+    egress: Option<Vec<PeerRule>>,
+    labels: BTreeMap<String, String>,
+    apply_options: &ApplyOptions,
+) -> Result<NetworkPolicy, crate::Error> {
+    let object = build(name, namespace, selector_labels, ingress, egress, labels)?;
+
+    event!(Level::INFO, name, namespace, "Creating NetworkPolicy");
+
+    let api: Api<NetworkPolicy> = Api::namespaced(client, namespace);
+    let params = apply_options.patch_params();
+    Ok(api.patch(name, &params, &Patch::Apply(&object)).await?)
+}
+
+fn build(
+    name: &str,
+    namespace: &str,
+    selector_labels: BTreeMap<String, String>,
+    ingress: Option<Vec<PeerRule>>,
+    egress: Option<Vec<PeerRule>>,
+    labels: BTreeMap<String, String>,
+) -> Result<NetworkPolicy, crate::Error> {
+    if ingress.is_none() && egress.is_none() {
+        return Err(crate::Error::UnrestrictedNetworkPolicy);
+    }
+
+    let mut policy_types = Vec::new();
+    if ingress.is_some() {
+        policy_types.push("Ingress".to_owned());
+    }
+    if egress.is_some() {
+        policy_types.push("Egress".to_owned());
+    }
+
+    Ok(NetworkPolicy {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            namespace: Some(namespace.to_owned()),
+            labels: Some(labels.clone()),
+            ..ObjectMeta::default()
+        },
+        spec: Some(NetworkPolicySpec {
+            pod_selector: Some(LabelSelector {
+                match_labels: Some(selector_labels),
+                ..LabelSelector::default()
+            }),
+            ingress: ingress.map(|rules| {
+                rules
+                    .iter()
+                    .map(|rule| NetworkPolicyIngressRule {
+                        from: rule.peers(),
+                        ports: rule.ports(),
+                    })
+                    .collect()
+            }),
+            egress: egress.map(|rules| {
+                rules
+                    .iter()
+                    .map(|rule| NetworkPolicyEgressRule {
+                        to: rule.peers(),
+                        ports: rule.ports(),
+                    })
+                    .collect()
+            }),
+            policy_types: Some(policy_types),
+        }),
+    })
+}
+
+#[instrument(skip(client))]
+pub async fn delete(
+    client: Client,
+    name: String,
+    namespace: String,
+    propagation_policy: Option<PropagationPolicy>,
+) -> Result<(), Error> {
+    event!(Level::INFO, name, namespace, "Deleting NetworkPolicy");
+
+    let api: Api<NetworkPolicy> = Api::namespaced(client, namespace.as_str());
+    let dp = DeleteParams {
+        propagation_policy,
+        ..DeleteParams::default()
+    };
+    // If the resource doesn't exist, we can ignore the error
+    ignore_not_found(api.delete(name.as_str(), &dp).await)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ingress_only_allows_swarm_port() {
+        let selector = BTreeMap::from([("app".to_owned(), "ipfs".to_owned())]);
+        let swarm = PeerRule {
+            peers: Vec::new(),
+            ports: vec![(4001, Protocol::Tcp)],
+        };
+
+        let policy = build(
+            "np",
+            "ns",
+            selector.clone(),
+            Some(vec![swarm]),
+            None,
+            BTreeMap::new(),
+        )
+        .unwrap();
+        let spec = policy.spec.unwrap();
+
+        assert_eq!(spec.pod_selector.unwrap().match_labels, Some(selector));
+        assert_eq!(spec.policy_types, Some(vec!["Ingress".to_owned()]));
+        assert_eq!(spec.egress, None);
+
+        let ingress = spec.ingress.unwrap();
+        assert_eq!(ingress.len(), 1);
+        assert_eq!(ingress[0].from, None);
+        assert_eq!(
+            ingress[0].ports,
+            Some(vec![NetworkPolicyPort {
+                port: Some(IntOrString::Int(4001)),
+                protocol: Some("TCP".to_owned()),
+                ..NetworkPolicyPort::default()
+            }])
+        );
+    }
+
+    #[test]
+    fn rejects_policy_restricting_nothing() {
+        let err = build("np", "ns", BTreeMap::new(), None, None, BTreeMap::new()).unwrap_err();
+        assert!(matches!(err, crate::Error::UnrestrictedNetworkPolicy));
+    }
+}
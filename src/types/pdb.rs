@@ -0,0 +1,109 @@
+use k8s_openapi::api::policy::v1::{PodDisruptionBudget, PodDisruptionBudgetSpec};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use kube::api::{DeleteParams, ObjectMeta, Patch, PropagationPolicy};
+use kube::{Api, Client, Error};
+use std::collections::BTreeMap;
+use tracing::{Level, event, instrument};
+
+use crate::{ApplyOptions, ignore_not_found};
+
+/// Limits voluntary disruptions (drains, evictions) of the pods matching
+/// `selector_labels`. Pass the output of `crate::selector_labels` so the
+/// budget covers the same pods as the Services.
+#[instrument(skip(client))]
+pub async fn deploy(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    min_available: IntOrString,
+    selector_labels: BTreeMap<String, String>,
+    apply_options: &ApplyOptions,
+) -> Result<PodDisruptionBudget, Error> {
+    let object: PodDisruptionBudget = PodDisruptionBudget {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            namespace: Some(namespace.to_owned()),
+            labels: Some(selector_labels.clone()),
+            ..ObjectMeta::default()
+        },
+        spec: Some(PodDisruptionBudgetSpec {
+            min_available: Some(min_available),
+            selector: Some(LabelSelector {
+                match_labels: Some(selector_labels),
+                ..LabelSelector::default()
+            }),
+            ..PodDisruptionBudgetSpec::default()
+        }),
+        ..PodDisruptionBudget::default()
+    };
+
+    event!(Level::INFO, name, namespace, "Creating PodDisruptionBudget");
+
+    let api: Api<PodDisruptionBudget> = Api::namespaced(client, namespace);
+    let params = apply_options.patch_params();
+    api.patch(name, &params, &Patch::Apply(&object)).await
+}
+
+#[instrument(skip(client))]
+pub async fn delete(
+    client: Client,
+    name: String,
+    namespace: String,
+    propagation_policy: Option<PropagationPolicy>,
+) -> Result<(), Error> {
+    event!(Level::INFO, name, namespace, "Deleting PodDisruptionBudget");
+
+    let api: Api<PodDisruptionBudget> = Api::namespaced(client, namespace.as_str());
+    let dp = DeleteParams {
+        propagation_policy,
+        ..DeleteParams::default()
+    };
+    // If the resource doesn't exist, we can ignore the error
+    ignore_not_found(api.delete(name.as_str(), &dp).await)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::mock_client;
+
+    #[tokio::test]
+    async fn deploy_selects_the_selector_labels() {
+        let selector = crate::selector_labels("inst".to_owned(), "node".to_owned()).unwrap();
+        let (client, mut server) = mock_client();
+        let deploy = tokio::spawn({
+            let selector = selector.clone();
+            async move {
+                deploy(
+                    client,
+                    "inst",
+                    "ns",
+                    IntOrString::Int(2),
+                    selector,
+                    &ApplyOptions::default(),
+                )
+                .await
+            }
+        });
+
+        let pdb = json!({
+            "apiVersion": "policy/v1",
+            "kind": "PodDisruptionBudget",
+            "metadata": { "name": "inst", "namespace": "ns" },
+        });
+        let body = server
+            .expect_patch(
+                "/apis/policy/v1/namespaces/ns/poddisruptionbudgets/inst",
+                pdb,
+            )
+            .await;
+        assert_eq!(body["spec"]["selector"]["matchLabels"], json!(selector));
+        assert_eq!(body["spec"]["minAvailable"], 2);
+
+        deploy.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+use k8s_openapi::api::rbac::v1::{PolicyRule, Role, RoleBinding, RoleRef, Subject};
+use kube::api::{DeleteParams, ObjectMeta, Patch, PropagationPolicy};
+use kube::{Api, Client, Error};
+use std::collections::BTreeMap;
+use tracing::{Level, event, instrument};
+
+use crate::{ApplyOptions, ignore_not_found};
+
+const RBAC_API_GROUP: &str = "rbac.authorization.k8s.io";
+
+#[instrument(skip(client))]
+pub async fn deploy_role(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    rules: Vec<PolicyRule>,
+    labels: BTreeMap<String, String>,
+    apply_options: &ApplyOptions,
+) -> Result<Role, Error> {
+    let object: Role = Role {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            namespace: Some(namespace.to_owned()),
+            labels: Some(labels.clone()),
+            ..ObjectMeta::default()
+        },
+        rules: Some(rules),
+    };
+
+    event!(Level::INFO, name, namespace, "Creating Role");
+
+    let api: Api<Role> = Api::namespaced(client, namespace);
+    let params = apply_options.patch_params();
+    api.patch(name, &params, &Patch::Apply(&object)).await
+}
+
+/// `role_ref` must name a `Role` or `ClusterRole`; an empty `api_group` is
+/// filled in with `rbac.authorization.k8s.io`
+#[instrument(skip(client))]
+pub async fn deploy_role_binding(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    mut role_ref: RoleRef,
+    subjects: Vec<Subject>,
+    labels: BTreeMap<String, String>,
+    apply_options: &ApplyOptions,
+) -> Result<RoleBinding, crate::Error> {
+    if !matches!(role_ref.kind.as_str(), "Role" | "ClusterRole") {
+        return Err(crate::Error::InvalidRoleRefKind(role_ref.kind));
+    }
+    if role_ref.api_group.is_empty() {
+        role_ref.api_group = RBAC_API_GROUP.to_owned();
+    }
+
+    let object: RoleBinding = RoleBinding {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            namespace: Some(namespace.to_owned()),
+            labels: Some(labels.clone()),
+            ..ObjectMeta::default()
+        },
+        role_ref,
+        subjects: (!subjects.is_empty()).then_some(subjects),
+    };
+
+    event!(Level::INFO, name, namespace, "Creating RoleBinding");
+
+    let api: Api<RoleBinding> = Api::namespaced(client, namespace);
+    let params = apply_options.patch_params();
+    Ok(api.patch(name, &params, &Patch::Apply(&object)).await?)
+}
+
+/// Subject for a ServiceAccount, e.g. one created with `service_account::deploy`
+pub fn service_account_subject(name: &str, namespace: &str) -> Subject {
+    Subject {
+        api_group: None,
+        kind: "ServiceAccount".to_owned(),
+        name: name.to_owned(),
+        namespace: Some(namespace.to_owned()),
+    }
+}
+
+#[instrument(skip(client))]
+pub async fn delete_role(
+    client: Client,
+    name: String,
+    namespace: String,
+    propagation_policy: Option<PropagationPolicy>,
+) -> Result<(), Error> {
+    event!(Level::INFO, name, namespace, "Deleting Role");
+
+    let api: Api<Role> = Api::namespaced(client, namespace.as_str());
+    let dp = DeleteParams {
+        propagation_policy,
+        ..DeleteParams::default()
+    };
+    // If the resource doesn't exist, we can ignore the error
+    ignore_not_found(api.delete(name.as_str(), &dp).await)?;
+    Ok(())
+}
+
+#[instrument(skip(client))]
+pub async fn delete_role_binding(
+    client: Client,
+    name: String,
+    namespace: String,
+    propagation_policy: Option<PropagationPolicy>,
+) -> Result<(), Error> {
+    event!(Level::INFO, name, namespace, "Deleting RoleBinding");
+
+    let api: Api<RoleBinding> = Api::namespaced(client, namespace.as_str());
+    let dp = DeleteParams {
+        propagation_policy,
+        ..DeleteParams::default()
+    };
+    // If the resource doesn't exist, we can ignore the error
+    ignore_not_found(api.delete(name.as_str(), &dp).await)?;
+    Ok(())
+}
@@ -1,21 +1,72 @@
 use k8s_openapi::ByteString;
 use k8s_openapi::api::core::v1::Secret;
-use kube::api::{DeleteParams, ObjectMeta, Patch, PatchParams};
+use kube::api::{DeleteParams, ObjectMeta, Patch, PropagationPolicy};
 use kube::{Api, Client, Error};
 use std::collections::BTreeMap;
+use std::fmt::Display;
 use tracing::{Level, event, instrument};
 
-#[instrument(skip(client))]
+use crate::{ApplyOptions, ignore_not_found};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub enum SecretType {
+    #[default]
+    Opaque,
+    Tls,
+}
+
+impl Display for SecretType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SecretType::Opaque => write!(f, "Opaque"),
+            SecretType::Tls => write!(f, "kubernetes.io/tls"),
+        }
+    }
+}
+
+#[instrument(skip(client, data))]
 pub async fn deploy(
     client: Client,
     name: &str,
     namespace: &str,
     data: BTreeMap<String, ByteString>,
+    secret_type: SecretType,
     labels: BTreeMap<String, String>,
+    apply_options: &ApplyOptions,
 ) -> Result<Secret, Error> {
     // Definition of the deployment. Alternatively, a YAML representation could be used as well.
     let object: Secret = Secret {
         data: Some(data),
+        type_: Some(secret_type.to_string()),
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            namespace: Some(namespace.to_owned()),
+            labels: Some(labels.clone()),
+            ..ObjectMeta::default()
+        },
+        ..Secret::default()
+    };
+
+    event!(Level::INFO, name, namespace, "Creating Secret");
+
+    apply(client, name, namespace, &object, apply_options).await
+}
+
+/// Like `deploy`, but takes plain strings via `stringData` and lets the API
+/// server do the base64 encoding
+#[instrument(skip(client, string_data))]
+pub async fn deploy_string_data(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    string_data: BTreeMap<String, String>,
+    secret_type: SecretType,
+    labels: BTreeMap<String, String>,
+    apply_options: &ApplyOptions,
+) -> Result<Secret, Error> {
+    let object: Secret = Secret {
+        string_data: Some(string_data),
+        type_: Some(secret_type.to_string()),
         metadata: ObjectMeta {
             name: Some(name.to_owned()),
             namespace: Some(namespace.to_owned()),
@@ -27,34 +78,50 @@ pub async fn deploy(
 
     event!(Level::INFO, name, namespace, "Creating Secret");
 
-    // Create the pvc defined above
+    apply(client, name, namespace, &object, apply_options).await
+}
+
+async fn apply(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    object: &Secret,
+    apply_options: &ApplyOptions,
+) -> Result<Secret, Error> {
     let service_api: Api<Secret> = Api::namespaced(client, namespace);
-    let params = PatchParams::apply(name);
+    let params = apply_options.patch_params();
     service_api
-        .patch(name, &params, &Patch::Apply(&object))
+        .patch(name, &params, &Patch::Apply(object))
         .await
 }
 
 #[instrument(skip(client))]
-pub async fn delete(client: Client, name: String, namespace: String) -> Result<(), Error> {
+pub async fn get_opt(
+    client: Client,
+    name: &str,
+    namespace: &str,
+) -> Result<Option<Secret>, crate::Error> {
+    let service_api: Api<Secret> = Api::namespaced(client, namespace);
+    Ok(service_api.get_opt(name).await?)
+}
+
+#[instrument(skip(client))]
+pub async fn delete(
+    client: Client,
+    name: String,
+    namespace: String,
+    propagation_policy: Option<PropagationPolicy>,
+) -> Result<(), Error> {
     event!(Level::INFO, name, namespace, "Deleting Secret");
 
     let api: Api<Secret> = Api::namespaced(client, namespace.as_str());
-    match api.delete(name.as_str(), &DeleteParams::default()).await {
-        Ok(_) => Ok(()),
-        Err(e) => {
-            match e {
-                // If the resource doesn't exist, we can ignore the error
-                Error::Api(er) => {
-                    if er.reason == "NotFound" {
-                        return Ok(());
-                    };
-                    Err(Error::Api(er))
-                }
-                _ => Err(e),
-            }
-        }
-    }
+    let dp = DeleteParams {
+        propagation_policy,
+        ..DeleteParams::default()
+    };
+    // If the resource doesn't exist, we can ignore the error
+    ignore_not_found(api.delete(name.as_str(), &dp).await)?;
+    Ok(())
 }
 
 #[instrument(skip(client))]
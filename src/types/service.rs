@@ -1,11 +1,18 @@
-use k8s_openapi::api::core::v1::{Service, ServicePort, ServiceSpec};
+use k8s_openapi::api::core::v1::{
+    ClientIPConfig, PodTemplateSpec, Service, ServicePort, ServiceSpec, SessionAffinityConfig,
+};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
 use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
-use kube::api::{DeleteParams, ListParams, ObjectMeta, Patch, PatchParams};
+use kube::api::{DeleteParams, ListParams, ObjectMeta, Patch, PatchParams, PropagationPolicy};
 use kube::{Api, Client, Error, ResourceExt};
-use std::collections::BTreeMap;
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fmt::Display;
+use std::net::IpAddr;
 use tracing::{Level, event, instrument};
 
+use crate::{ApplyOptions, DeleteResult, ignore_not_found};
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ServiceType {
     ClusterIP,
@@ -23,14 +30,237 @@ impl Display for ServiceType {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TrafficPolicy {
+    Cluster,
+    Local,
+}
+
+impl Display for TrafficPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrafficPolicy::Cluster => write!(f, "Cluster"),
+            TrafficPolicy::Local => write!(f, "Local"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SessionAffinity {
+    None,
+    ClientIP,
+}
+
+impl Display for SessionAffinity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionAffinity::None => write!(f, "None"),
+            SessionAffinity::ClientIP => write!(f, "ClientIP"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    #[default]
+    Tcp,
+    Udp,
+    Sctp,
+}
+
+impl Protocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Protocol::Tcp => "TCP",
+            Protocol::Udp => "UDP",
+            Protocol::Sctp => "SCTP",
+        }
+    }
+}
+
+impl Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IpFamily {
+    Ipv4,
+    Ipv6,
+}
+
+impl IpFamily {
+    /// Family of an IP address string; `None` for hostnames
+    pub fn of(address: &str) -> Option<Self> {
+        match address.parse::<IpAddr>() {
+            Ok(IpAddr::V4(_)) => Some(IpFamily::Ipv4),
+            Ok(IpAddr::V6(_)) => Some(IpFamily::Ipv6),
+            Err(_) => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IpFamily::Ipv4 => "IPv4",
+            IpFamily::Ipv6 => "IPv6",
+        }
+    }
+}
+
+impl Display for IpFamily {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum IpFamilyPolicy {
+    SingleStack,
+    PreferDualStack,
+    RequireDualStack,
+}
+
+impl Display for IpFamilyPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IpFamilyPolicy::SingleStack => write!(f, "SingleStack"),
+            IpFamilyPolicy::PreferDualStack => write!(f, "PreferDualStack"),
+            IpFamilyPolicy::RequireDualStack => write!(f, "RequireDualStack"),
+        }
+    }
+}
+
+/// Default `--service-node-port-range` of the API server
+const NODE_PORT_RANGE: std::ops::RangeInclusive<i32> = 30000..=32767;
+
 #[derive(Debug, Default, Clone)]
 pub struct Port {
     pub name: String,
     pub port: i32,
-    pub target_port: IntOrString,
-    pub protocol: String,
+    /// Numeric or named container port; defaults to `port` when unset
+    pub target_port: Option<IntOrString>,
+    pub protocol: Protocol,
+    pub node_port: Option<i32>,
+    /// Hint for meshes and ingress controllers, e.g. `http`, `https` or
+    /// `kubernetes.io/h2c`
+    pub app_protocol: Option<String>,
+}
+
+impl Port {
+    pub fn tcp(name: &str, port: i32) -> Self {
+        Self::new(name, port, Protocol::Tcp)
+    }
+
+    pub fn udp(name: &str, port: i32) -> Self {
+        Self::new(name, port, Protocol::Udp)
+    }
+
+    pub fn new(name: &str, port: i32, protocol: Protocol) -> Self {
+        Self {
+            name: name.to_owned(),
+            port,
+            protocol,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_target_port(mut self, target_port: IntOrString) -> Self {
+        self.target_port = Some(target_port);
+        self
+    }
+
+    pub fn with_node_port(mut self, node_port: i32) -> Self {
+        self.node_port = Some(node_port);
+        self
+    }
+
+    pub fn with_app_protocol(mut self, app_protocol: &str) -> Self {
+        self.app_protocol = Some(app_protocol.to_owned());
+        self
+    }
+}
+
+/// Optional Service settings beyond the type, ports and labels
+#[derive(Debug, Default, Clone)]
+pub struct ServiceOptions {
+    /// Set on `metadata.annotations`; cloud LoadBalancer behavior is mostly
+    /// configured this way (e.g. `service.beta.kubernetes.io/aws-load-balancer-type`)
+    pub annotations: BTreeMap<String, String>,
+    /// `Local` preserves the client source IP, which matters for p2p traffic
+    pub external_traffic_policy: Option<TrafficPolicy>,
+    /// `Local` keeps in-cluster traffic on the client's node, e.g. for a
+    /// node-local peer
+    pub internal_traffic_policy: Option<TrafficPolicy>,
+    /// CIDRs allowed to reach a LoadBalancer; empty leaves access unrestricted
+    pub load_balancer_source_ranges: Vec<String>,
+    /// Lets the Service be garbage-collected along with its owner
+    pub owner: Option<OwnerReference>,
+    /// For LoadBalancer Services, re-request the address the live Service
+    /// currently has, so re-applies keep announced addresses stable. When
+    /// there is no live address, e.g. the Service was deleted, `previous_ip`
+    /// is requested instead.
+    pub preserve_ip: Option<IpPinning>,
+    /// Address to request under `preserve_ip` when the live Service has none.
+    /// `load_balancer::deploy` fills this in per replica from the addresses
+    /// last published with `publish_external_addresses`.
+    pub previous_ip: Option<IpAddr>,
+    /// Publish DNS records for pods before they pass readiness, so p2p peers
+    /// behind a headless Service can find each other while starting up
+    pub publish_not_ready_addresses: bool,
+    /// `ClientIP` sends each client to the same pod, e.g. for sticky gateway
+    /// sessions
+    pub session_affinity: Option<SessionAffinity>,
+    /// How long `ClientIP` affinity sticks; the API server defaults to 3 hours.
+    /// Only valid with `session_affinity` set to `ClientIP`.
+    pub session_affinity_timeout_seconds: Option<i32>,
+    /// `PreferDualStack` gets IPv6-capable peers an address in both families
+    /// where the cluster supports it
+    pub ip_family_policy: Option<IpFamilyPolicy>,
+    /// Families in order of preference; empty leaves the cluster default
+    pub ip_families: Vec<IpFamily>,
+    /// Picks the LoadBalancer implementation when a cluster runs several,
+    /// e.g. `metallb.io/metallb`. Only valid for LoadBalancer Services.
+    pub load_balancer_class: Option<String>,
+    /// `Some(false)` stops a LoadBalancer Service from also taking a nodePort,
+    /// so a LoadBalancer per pod doesn't exhaust the nodePort range. Only
+    /// valid for LoadBalancer Services, and only with implementations that
+    /// route to pods directly.
+    pub allocate_load_balancer_node_ports: Option<bool>,
+    /// Pins the nodePort cloud health checks probe, so firewall rules can
+    /// allow it ahead of time. Only valid for LoadBalancer Services with an
+    /// `external_traffic_policy` of `Local`.
+    pub health_check_node_port: Option<i32>,
+    /// Named ports the selected pods expose (see `container_port_names`).
+    /// When set, a named `target_port` missing from it is rejected rather
+    /// than producing a Service that routes nowhere.
+    pub container_ports: Option<BTreeSet<String>>,
+}
+
+/// Annotation MetalLB reads to pick the address pool for a LoadBalancer.
+/// MetalLB 0.14+ also accepts `metallb.io/address-pool`.
+pub const METALLB_ADDRESS_POOL: &str = "metallb.universe.tf/address-pool";
+
+impl ServiceOptions {
+    /// Has MetalLB allocate the LoadBalancer address from the `pool` pool
+    pub fn with_address_pool(mut self, pool: &str) -> Self {
+        self.annotations
+            .insert(METALLB_ADDRESS_POOL.to_owned(), pool.to_owned());
+        self
+    }
 }
 
+/// How to request a specific LoadBalancer address
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum IpPinning {
+    /// `spec.loadBalancerIP`. Deprecated since Kubernetes 1.24 and ignored by
+    /// some providers, which want an annotation instead.
+    Spec,
+    /// A provider-specific annotation, e.g. `metallb.universe.tf/loadBalancerIPs`
+    Annotation(String),
+}
+
+#[allow(clippy::too_many_arguments)]
 #[instrument(skip(client))]
 pub async fn deploy(
     client: Client,
@@ -39,66 +269,457 @@ pub async fn deploy(
     service_type: ServiceType,
     service_port: Vec<Port>,
     labels: (BTreeMap<String, String>, BTreeMap<String, String>),
-) -> Result<Service, Error> {
-    let mut service_ports: Vec<ServicePort> = Vec::new();
+    options: ServiceOptions,
+    apply_options: &ApplyOptions,
+) -> Result<Service, crate::Error> {
+    let pinning = match service_type {
+        ServiceType::LoadBalancer => options.preserve_ip.clone(),
+        _ => None,
+    };
+    let previous_ip = options.previous_ip;
+    let reuse_cluster_ip = service_type == ServiceType::ClusterIP;
 
-    for port in service_port {
-        service_ports.push(ServicePort {
-            name: Some(port.name),
-            port: port.port,
-            protocol: Some(port.protocol.to_string()),
-            target_port: Some(port.target_port),
-            ..ServicePort::default()
-        });
+    let mut object = build(
+        &name,
+        &namespace,
+        service_type,
+        service_port,
+        labels,
+        options,
+    )?;
+
+    if let Some(pinning) = pinning {
+        pin_current_ip(
+            client.clone(),
+            &name,
+            &namespace,
+            &pinning,
+            previous_ip,
+            &mut object,
+        )
+        .await?;
+    }
+    if reuse_cluster_ip {
+        keep_cluster_ip(client.clone(), &name, &namespace, &mut object).await?;
+    }
+
+    event!(Level::INFO, name, namespace, "Creating Service");
+
+    apply(client, &name, &namespace, &object, apply_options).await
+}
+
+/// Deploys a headless Service (`clusterIP: None`), such as the governing
+/// Service a StatefulSet needs for stable per-pod DNS names.
+#[instrument(skip(client))]
+pub async fn deploy_headless(
+    client: Client,
+    name: String,
+    namespace: String,
+    service_port: Vec<Port>,
+    labels: (BTreeMap<String, String>, BTreeMap<String, String>),
+    options: ServiceOptions,
+    apply_options: &ApplyOptions,
+) -> Result<Service, crate::Error> {
+    let mut object = build(
+        &name,
+        &namespace,
+        ServiceType::ClusterIP,
+        service_port,
+        labels,
+        options,
+    )?;
+    if let Some(spec) = object.spec.as_mut() {
+        spec.cluster_ip = Some("None".to_owned());
     }
 
-    let object: Service = Service {
+    event!(Level::INFO, name, namespace, "Creating headless Service");
+
+    apply(client, &name, &namespace, &object, apply_options).await
+}
+
+/// Deploys an `ExternalName` Service, a DNS CNAME to `external_name` (e.g. an
+/// external IPFS gateway). Such Services have no ports or selector.
+#[instrument(skip(client))]
+pub async fn deploy_external_name(
+    client: Client,
+    name: String,
+    namespace: String,
+    external_name: String,
+    labels: BTreeMap<String, String>,
+    apply_options: &ApplyOptions,
+) -> Result<Service, crate::Error> {
+    let object = Service {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            namespace: Some(namespace.to_owned()),
+            labels: Some(labels),
+            ..ObjectMeta::default()
+        },
+        spec: Some(ServiceSpec {
+            type_: Some("ExternalName".to_owned()),
+            external_name: Some(external_name),
+            ..ServiceSpec::default()
+        }),
+        ..Service::default()
+    };
+
+    event!(
+        Level::INFO,
+        name,
+        namespace,
+        "Creating ExternalName Service"
+    );
+
+    apply(client, &name, &namespace, &object, apply_options).await
+}
+
+/// Checks the ports can coexist in one Service: names must be unique (e.g. a
+/// TCP and a UDP port sharing a number need distinct names), set once there is
+/// more than one port, and any pinned nodePort must be in range
+pub fn validate_ports(ports: &[Port]) -> Result<(), crate::Error> {
+    let mut names = HashSet::new();
+
+    for port in ports {
+        if ports.len() > 1 && port.name.is_empty() {
+            return Err(crate::Error::MissingPortName);
+        }
+
+        if !names.insert(port.name.as_str()) {
+            return Err(crate::Error::DuplicatePortName(port.name.clone()));
+        }
+
+        if let Some(node_port) = port.node_port
+            && !NODE_PORT_RANGE.contains(&node_port)
+        {
+            return Err(crate::Error::InvalidNodePort(node_port));
+        }
+    }
+
+    Ok(())
+}
+
+/// Names of the container ports declared in a pod template, e.g. the
+/// StatefulSet's, for `ServiceOptions::container_ports`
+pub fn container_port_names(template: &PodTemplateSpec) -> BTreeSet<String> {
+    template
+        .spec
+        .iter()
+        .flat_map(|spec| &spec.containers)
+        .flat_map(|container| container.ports.iter().flatten())
+        .filter_map(|port| port.name.clone())
+        .collect()
+}
+
+fn validate_target_ports(ports: &[Port], known: &BTreeSet<String>) -> Result<(), crate::Error> {
+    for port in ports {
+        if let Some(IntOrString::String(target)) = &port.target_port
+            && !known.contains(target)
+        {
+            return Err(crate::Error::UnknownTargetPort(target.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Checks the options make sense for `service_type` and `ports` before
+/// anything is sent, since the API server's rejections of these are opaque.
+/// `validate_ports` covers the ports on their own.
+pub fn validate_options(
+    service_type: &ServiceType,
+    options: &ServiceOptions,
+    ports: &[Port],
+) -> Result<(), crate::Error> {
+    if let Some(range) = options
+        .load_balancer_source_ranges
+        .iter()
+        .find(|range| !is_cidr(range))
+    {
+        return Err(crate::Error::InvalidCidr(range.to_owned()));
+    }
+
+    if *service_type != ServiceType::LoadBalancer {
+        if options.load_balancer_class.is_some() {
+            return Err(crate::Error::LoadBalancerOnly(
+                "loadBalancerClass".to_owned(),
+            ));
+        }
+        if options.allocate_load_balancer_node_ports.is_some() {
+            return Err(crate::Error::LoadBalancerOnly(
+                "allocateLoadBalancerNodePorts".to_owned(),
+            ));
+        }
+        if options.health_check_node_port.is_some() {
+            return Err(crate::Error::LoadBalancerOnly(
+                "healthCheckNodePort".to_owned(),
+            ));
+        }
+    }
+    if let Some(port) = options.health_check_node_port {
+        if options.external_traffic_policy != Some(TrafficPolicy::Local) {
+            return Err(crate::Error::HealthCheckNodePortRequiresLocal);
+        }
+        if !NODE_PORT_RANGE.contains(&port) {
+            return Err(crate::Error::InvalidNodePort(port));
+        }
+    }
+
+    if options.session_affinity_timeout_seconds.is_some()
+        && options.session_affinity != Some(SessionAffinity::ClientIP)
+    {
+        return Err(crate::Error::ClientIpAffinityOnly);
+    }
+
+    if let Some(known) = &options.container_ports {
+        validate_target_ports(ports, known)?;
+    }
+    Ok(())
+}
+
+fn build(
+    name: &str,
+    namespace: &str,
+    service_type: ServiceType,
+    service_port: Vec<Port>,
+    labels: (BTreeMap<String, String>, BTreeMap<String, String>),
+    options: ServiceOptions,
+) -> Result<Service, crate::Error> {
+    validate_ports(&service_port)?;
+    validate_options(&service_type, &options, &service_port)?;
+    let service_ports = to_service_ports(service_port);
+
+    Ok(Service {
         metadata: ObjectMeta {
             name: Some(name.to_owned()),
             namespace: Some(namespace.to_owned()),
             labels: Some(labels.0.clone()),
+            annotations: Some(options.annotations),
+            owner_references: options.owner.map(|owner| vec![owner]),
             ..ObjectMeta::default()
         },
         spec: Some(ServiceSpec {
             type_: Some(service_type.to_string()),
             ports: Some(service_ports),
             selector: Some(labels.1),
+            external_traffic_policy: options.external_traffic_policy.map(|p| p.to_string()),
+            internal_traffic_policy: options.internal_traffic_policy.map(|p| p.to_string()),
+            load_balancer_source_ranges: (!options.load_balancer_source_ranges.is_empty())
+                .then_some(options.load_balancer_source_ranges),
+            publish_not_ready_addresses: options.publish_not_ready_addresses.then_some(true),
+            load_balancer_class: options.load_balancer_class,
+            allocate_load_balancer_node_ports: options.allocate_load_balancer_node_ports,
+            health_check_node_port: options.health_check_node_port,
+            ip_family_policy: options.ip_family_policy.map(|p| p.to_string()),
+            ip_families: (!options.ip_families.is_empty()).then(|| {
+                options
+                    .ip_families
+                    .iter()
+                    .map(|family| family.to_string())
+                    .collect()
+            }),
+            session_affinity: options.session_affinity.map(|a| a.to_string()),
+            session_affinity_config: options.session_affinity_timeout_seconds.map(|timeout| {
+                SessionAffinityConfig {
+                    client_ip: Some(ClientIPConfig {
+                        timeout_seconds: Some(timeout),
+                    }),
+                }
+            }),
             ..ServiceSpec::default()
         }),
         ..Service::default()
+    })
+}
+
+/// Carries the live Service's cluster IPs into `object`, so a re-apply never
+/// gets a new one and in-cluster clients holding the old IP keep working
+async fn keep_cluster_ip(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    object: &mut Service,
+) -> Result<(), crate::Error> {
+    let Some(live) = get_opt(client, name, namespace)
+        .await?
+        .and_then(|svc| svc.spec)
+    else {
+        return Ok(());
     };
 
-    event!(Level::INFO, name, namespace, "Creating Service");
+    // A headless Service can't become a normal one in place anyway
+    if live.cluster_ip.is_none() || live.cluster_ip.as_deref() == Some("None") {
+        return Ok(());
+    }
+
+    if let Some(spec) = object.spec.as_mut() {
+        spec.cluster_ip = live.cluster_ip;
+        spec.cluster_ips = live.cluster_ips;
+    }
+
+    Ok(())
+}
+
+async fn pin_current_ip(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    pinning: &IpPinning,
+    previous_ip: Option<IpAddr>,
+    object: &mut Service,
+) -> Result<(), crate::Error> {
+    let current_ip = get_opt(client, name, namespace)
+        .await?
+        .and_then(|svc| svc.status)
+        .and_then(|status| status.load_balancer)
+        .and_then(|lb| lb.ingress)
+        .and_then(|ingress| ingress.into_iter().find_map(|i| i.ip))
+        .or_else(|| previous_ip.map(|ip| ip.to_string()));
+
+    let Some(ip) = current_ip else {
+        return Ok(());
+    };
+
+    match pinning {
+        IpPinning::Spec => {
+            if let Some(spec) = object.spec.as_mut() {
+                spec.load_balancer_ip = Some(ip);
+            }
+        }
+        IpPinning::Annotation(key) => {
+            object
+                .metadata
+                .annotations
+                .get_or_insert_with(BTreeMap::new)
+                .insert(key.to_owned(), ip);
+        }
+    }
+
+    Ok(())
+}
+
+fn to_service_ports(service_port: Vec<Port>) -> Vec<ServicePort> {
+    let mut service_ports: Vec<ServicePort> = Vec::new();
+
+    for port in service_port {
+        service_ports.push(ServicePort {
+            name: Some(port.name),
+            port: port.port,
+            protocol: Some(port.protocol.to_string()),
+            target_port: Some(port.target_port.unwrap_or(IntOrString::Int(port.port))),
+            node_port: port.node_port,
+            app_protocol: port.app_protocol,
+        });
+    }
+
+    service_ports
+}
+
+fn is_cidr(range: &str) -> bool {
+    let Some((addr, prefix)) = range.split_once('/') else {
+        return false;
+    };
+    let Ok(prefix) = prefix.parse::<u8>() else {
+        return false;
+    };
+    match addr.parse::<IpAddr>() {
+        Ok(IpAddr::V4(_)) => prefix <= 32,
+        Ok(IpAddr::V6(_)) => prefix <= 128,
+        Err(_) => false,
+    }
+}
+
+async fn apply(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    object: &Service,
+    apply_options: &ApplyOptions,
+) -> Result<Service, crate::Error> {
+    let service_api: Api<Service> = Api::namespaced(client, namespace);
+    let params = apply_options.patch_params();
+    Ok(service_api
+        .patch(name, &params, &Patch::Apply(object))
+        .await?)
+}
+
+/// Replaces only `spec.ports` of an existing Service. Unlike deleting and
+/// recreating it, this keeps the allocated LoadBalancer IP. NodePorts that
+/// aren't pinned on the new ports may be reallocated.
+#[instrument(skip(client))]
+pub async fn update_ports(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    ports: Vec<Port>,
+) -> Result<Service, crate::Error> {
+    validate_ports(&ports)?;
+
+    event!(Level::INFO, name, namespace, "Updating Service ports");
+
+    let service_api: Api<Service> = Api::namespaced(client, namespace);
+    // A JSON merge patch replaces the list wholesale, whereas a strategic
+    // merge would key entries by port number and keep the old ones
+    let patch = json!({ "spec": { "ports": to_service_ports(ports) } });
+    Ok(service_api
+        .patch(name, &PatchParams::default(), &Patch::Merge(&patch))
+        .await?)
+}
 
-    let service_api: Api<Service> = Api::namespaced(client, namespace.as_str());
-    let params = PatchParams::apply(&name);
-    service_api
-        .patch(&name, &params, &Patch::Apply(&object))
-        .await
+#[instrument(skip(client))]
+pub async fn get(client: Client, name: &str, namespace: &str) -> Result<Service, crate::Error> {
+    match get_opt(client, name, namespace).await? {
+        Some(res) => Ok(res),
+        None => Err(crate::Error::ServiceError(format!(
+            "Service {name} not found"
+        ))),
+    }
 }
 
 #[instrument(skip(client))]
-pub async fn delete(client: Client, name: String, namespace: String) -> Result<(), Error> {
+pub async fn get_opt(
+    client: Client,
+    name: &str,
+    namespace: &str,
+) -> Result<Option<Service>, crate::Error> {
+    let api: Api<Service> = Api::namespaced(client, namespace);
+    Ok(api.get_opt(name).await?)
+}
+
+#[instrument(skip(client))]
+pub async fn delete(
+    client: Client,
+    name: String,
+    namespace: String,
+    propagation_policy: Option<PropagationPolicy>,
+) -> Result<DeleteResult, Error> {
     event!(Level::INFO, name, namespace, "Deleting Service");
 
     let api: Api<Service> = Api::namespaced(client, namespace.as_str());
-    match api.delete(name.as_str(), &DeleteParams::default()).await {
-        Ok(_) => Ok(()),
-        Err(e) => {
-            match e {
-                // If the resource doesn't exist, we can ignore the error
-                Error::Api(er) => {
-                    if er.reason == "NotFound" {
-                        return Ok(());
-                    };
-                    Err(Error::Api(er))
-                }
-                _ => Err(e),
-            }
-        }
+    let dp = DeleteParams {
+        propagation_policy,
+        ..DeleteParams::default()
+    };
+    match ignore_not_found(api.delete(name.as_str(), &dp).await)? {
+        Some(res) => Ok(DeleteResult::from(res)),
+        None => Ok(DeleteResult::NotFound),
     }
 }
 
+/// Lists the LoadBalancer Services belonging to the `name` instance
+#[instrument(skip(client))]
+pub async fn list_load_balancers(
+    client: Client,
+    name: &str,
+    namespace: &str,
+) -> Result<Vec<Service>, Error> {
+    let service_api: Api<Service> = Api::namespaced(client, namespace);
+    let lp = ListParams::default()
+        .match_any()
+        .timeout(300)
+        .labels(format!("app.kubernetes.io/instance={name}").as_str())
+        .fields("spec.type=LoadBalancer");
+    Ok(service_api.list(&lp).await?.items)
+}
+
 #[instrument(skip(client))]
 pub async fn delete_cluster_ips(
     client: Client,
@@ -114,8 +735,365 @@ pub async fn delete_cluster_ips(
     let existing_services = service_api.list(&lp).await?;
 
     for svc in existing_services {
-        delete(client.clone(), svc.name_any(), namespace.clone()).await?;
+        delete(client.clone(), svc.name_any(), namespace.clone(), None).await?;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use http::{Method, StatusCode};
+
+    use super::*;
+    use crate::testing::mock_client;
+
+    fn service_json(name: &str) -> serde_json::Value {
+        json!({
+            "apiVersion": "v1",
+            "kind": "Service",
+            "metadata": { "name": name, "namespace": "ns" },
+        })
+    }
+
+    fn local_health_check(port: i32) -> ServiceOptions {
+        ServiceOptions {
+            external_traffic_policy: Some(TrafficPolicy::Local),
+            health_check_node_port: Some(port),
+            ..ServiceOptions::default()
+        }
+    }
+
+    #[test]
+    fn health_check_node_port_is_set_on_local_load_balancers() {
+        let options = local_health_check(31000);
+        validate_options(&ServiceType::LoadBalancer, &options, &[]).unwrap();
+
+        let svc = build(
+            "svc",
+            "ns",
+            ServiceType::LoadBalancer,
+            vec![Port::tcp("p2p", 4001)],
+            (BTreeMap::new(), BTreeMap::new()),
+            options,
+        )
+        .unwrap();
+        assert_eq!(svc.spec.unwrap().health_check_node_port, Some(31000));
+    }
+
+    #[test]
+    fn health_check_node_port_requires_local_policy() {
+        let options = ServiceOptions {
+            external_traffic_policy: Some(TrafficPolicy::Cluster),
+            ..local_health_check(31000)
+        };
+        let err = validate_options(&ServiceType::LoadBalancer, &options, &[]).unwrap_err();
+        assert!(matches!(
+            err,
+            crate::Error::HealthCheckNodePortRequiresLocal
+        ));
+
+        let options = ServiceOptions {
+            external_traffic_policy: None,
+            ..local_health_check(31000)
+        };
+        let err = validate_options(&ServiceType::LoadBalancer, &options, &[]).unwrap_err();
+        assert!(matches!(
+            err,
+            crate::Error::HealthCheckNodePortRequiresLocal
+        ));
+    }
+
+    #[test]
+    fn health_check_node_port_requires_load_balancer() {
+        let err =
+            validate_options(&ServiceType::NodePort, &local_health_check(31000), &[]).unwrap_err();
+        assert!(
+            matches!(err, crate::Error::LoadBalancerOnly(field) if field == "healthCheckNodePort")
+        );
+    }
+
+    #[test]
+    fn session_affinity_timeout_requires_client_ip() {
+        let options = ServiceOptions {
+            session_affinity_timeout_seconds: Some(600),
+            ..ServiceOptions::default()
+        };
+        let err = validate_options(&ServiceType::ClusterIP, &options, &[]).unwrap_err();
+        assert!(matches!(err, crate::Error::ClientIpAffinityOnly));
+
+        let options = ServiceOptions {
+            session_affinity: Some(SessionAffinity::ClientIP),
+            ..options
+        };
+        validate_options(&ServiceType::ClusterIP, &options, &[]).unwrap();
+    }
+
+    #[test]
+    fn health_check_node_port_must_be_in_range() {
+        let err = validate_options(&ServiceType::LoadBalancer, &local_health_check(8080), &[])
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::InvalidNodePort(8080)));
+    }
+
+    #[tokio::test]
+    async fn deploy_is_idempotent() {
+        let (client, mut server) = mock_client();
+        let deploy = tokio::spawn(async move {
+            for _ in 0..2 {
+                deploy(
+                    client.clone(),
+                    "svc".to_owned(),
+                    "ns".to_owned(),
+                    ServiceType::LoadBalancer,
+                    vec![Port::tcp("p2p", 4001)],
+                    (BTreeMap::new(), BTreeMap::new()),
+                    ServiceOptions::default(),
+                    &ApplyOptions::default(),
+                )
+                .await?;
+            }
+            Ok::<_, crate::Error>(())
+        });
+
+        for _ in 0..2 {
+            let (query, _) = server
+                .expect_with_query(
+                    Method::PATCH,
+                    "/api/v1/namespaces/ns/services/svc",
+                    StatusCode::OK,
+                    service_json("svc"),
+                )
+                .await;
+            assert!(query.contains("fieldManager=ipfs-operator"), "{query}");
+        }
+
+        deploy.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn target_port_can_name_a_container_port() {
+        let ports = to_service_ports(vec![
+            Port::tcp("p2p", 4001).with_target_port(IntOrString::String("p2p".to_owned())),
+            Port::tcp("api", 5001),
+        ]);
+        assert_eq!(
+            ports[0].target_port,
+            Some(IntOrString::String("p2p".to_owned()))
+        );
+        assert_eq!(ports[1].target_port, Some(IntOrString::Int(5001)));
+    }
+
+    #[test]
+    fn external_traffic_policy_is_set_when_requested() {
+        let build_with = |policy| {
+            let options = ServiceOptions {
+                external_traffic_policy: policy,
+                ..ServiceOptions::default()
+            };
+            build(
+                "svc",
+                "ns",
+                ServiceType::LoadBalancer,
+                vec![Port::tcp("p2p", 4001)],
+                (BTreeMap::new(), BTreeMap::new()),
+                options,
+            )
+            .unwrap()
+            .spec
+            .unwrap()
+        };
+        assert_eq!(
+            build_with(Some(TrafficPolicy::Local))
+                .external_traffic_policy
+                .as_deref(),
+            Some("Local")
+        );
+        assert_eq!(build_with(None).external_traffic_policy, None);
+    }
+
+    #[test]
+    fn tcp_and_udp_share_a_port_number() {
+        let svc = build(
+            "svc",
+            "ns",
+            ServiceType::LoadBalancer,
+            vec![Port::tcp("p2p", 4001), Port::udp("p2p-quic", 4001)],
+            (BTreeMap::new(), BTreeMap::new()),
+            ServiceOptions::default(),
+        )
+        .unwrap();
+        let ports = svc.spec.unwrap().ports.unwrap();
+        let ports: Vec<_> = ports
+            .iter()
+            .map(|p| (p.name.as_deref(), p.port, p.protocol.as_deref()))
+            .collect();
+        assert_eq!(
+            ports,
+            [
+                (Some("p2p"), 4001, Some("TCP")),
+                (Some("p2p-quic"), 4001, Some("UDP")),
+            ]
+        );
+
+        let err = build(
+            "svc",
+            "ns",
+            ServiceType::LoadBalancer,
+            vec![Port::tcp("p2p", 4001), Port::udp("p2p", 4001)],
+            (BTreeMap::new(), BTreeMap::new()),
+            ServiceOptions::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, crate::Error::DuplicatePortName(name) if name == "p2p"));
+    }
+
+    #[test]
+    fn port_names_must_be_unique_and_set() {
+        let err = validate_ports(&[Port::tcp("api", 5001), Port::tcp("api", 5002)]).unwrap_err();
+        assert!(matches!(err, crate::Error::DuplicatePortName(name) if name == "api"));
+
+        let err = validate_ports(&[Port::tcp("", 4001), Port::tcp("api", 5001)]).unwrap_err();
+        assert!(matches!(err, crate::Error::MissingPortName));
+
+        // A lone port may stay unnamed
+        validate_ports(&[Port::tcp("", 4001)]).unwrap();
+    }
+
+    #[tokio::test]
+    async fn list_load_balancers_selects_the_instances_load_balancers() {
+        let (client, mut server) = mock_client();
+        let list = tokio::spawn(async move { list_load_balancers(client, "inst", "ns").await });
+
+        let services = json!({
+            "apiVersion": "v1",
+            "kind": "ServiceList",
+            "metadata": {},
+            "items": [service_json("inst-0")],
+        });
+        let (query, _) = server
+            .expect_with_query(
+                Method::GET,
+                "/api/v1/namespaces/ns/services",
+                StatusCode::OK,
+                services,
+            )
+            .await;
+        assert!(
+            query.contains("labelSelector=app.kubernetes.io%2Finstance%3Dinst"),
+            "{query}"
+        );
+        assert!(
+            query.contains("fieldSelector=spec.type%3DLoadBalancer"),
+            "{query}"
+        );
+
+        let services = list.await.unwrap().unwrap();
+        assert_eq!(services.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn headless_service_can_publish_not_ready_addresses() {
+        let (client, mut server) = mock_client();
+        let deploy = tokio::spawn(async move {
+            let options = ServiceOptions {
+                publish_not_ready_addresses: true,
+                ..ServiceOptions::default()
+            };
+            deploy_headless(
+                client,
+                "ipfs".to_owned(),
+                "ns".to_owned(),
+                vec![Port::tcp("p2p", 4001)],
+                (BTreeMap::new(), BTreeMap::new()),
+                options,
+                &ApplyOptions::default(),
+            )
+            .await
+        });
+
+        let body = server
+            .expect_patch("/api/v1/namespaces/ns/services/ipfs", service_json("ipfs"))
+            .await;
+        assert_eq!(body["spec"]["clusterIP"], "None");
+        assert_eq!(body["spec"]["publishNotReadyAddresses"], true);
+        deploy.await.unwrap().unwrap();
+
+        let spec = build(
+            "ipfs",
+            "ns",
+            ServiceType::ClusterIP,
+            vec![Port::tcp("p2p", 4001)],
+            (BTreeMap::new(), BTreeMap::new()),
+            ServiceOptions::default(),
+        )
+        .unwrap()
+        .spec
+        .unwrap();
+        assert_eq!(spec.publish_not_ready_addresses, None);
+    }
+
+    #[tokio::test]
+    async fn external_name_service_has_no_ports_or_selector() {
+        let (client, mut server) = mock_client();
+        let deploy = tokio::spawn(async move {
+            deploy_external_name(
+                client,
+                "gateway".to_owned(),
+                "ns".to_owned(),
+                "gateway.example.com".to_owned(),
+                BTreeMap::new(),
+                &ApplyOptions::default(),
+            )
+            .await
+        });
+
+        let body = server
+            .expect_patch(
+                "/api/v1/namespaces/ns/services/gateway",
+                service_json("gateway"),
+            )
+            .await;
+        assert_eq!(
+            body["spec"],
+            json!({ "type": "ExternalName", "externalName": "gateway.example.com" })
+        );
+        deploy.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn load_balancer_class_is_set_on_load_balancers_only() {
+        let options = ServiceOptions {
+            load_balancer_class: Some("metallb.io/metallb".to_owned()),
+            ..ServiceOptions::default()
+        };
+        let svc = build(
+            "svc",
+            "ns",
+            ServiceType::LoadBalancer,
+            vec![Port::tcp("p2p", 4001)],
+            (BTreeMap::new(), BTreeMap::new()),
+            options.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            svc.spec.unwrap().load_balancer_class.as_deref(),
+            Some("metallb.io/metallb")
+        );
+
+        let err = validate_options(&ServiceType::NodePort, &options, &[]).unwrap_err();
+        assert!(
+            matches!(err, crate::Error::LoadBalancerOnly(field) if field == "loadBalancerClass")
+        );
+    }
+
+    #[test]
+    fn app_protocol_reaches_the_service_port() {
+        let ports = to_service_ports(vec![
+            Port::tcp("gateway", 8080).with_app_protocol("http"),
+            Port::tcp("p2p", 4001),
+        ]);
+        assert_eq!(ports[0].app_protocol.as_deref(), Some("http"));
+        assert_eq!(ports[1].app_protocol, None);
+    }
+}
@@ -0,0 +1,62 @@
+use k8s_openapi::api::core::v1::{LocalObjectReference, ServiceAccount};
+use kube::api::{DeleteParams, ObjectMeta, Patch, PropagationPolicy};
+use kube::{Api, Client, Error};
+use std::collections::BTreeMap;
+use tracing::{Level, event, instrument};
+
+use crate::{ApplyOptions, ignore_not_found};
+
+/// `image_pull_secrets` names Secrets in the same namespace that pods running
+/// as this account use to pull images
+#[instrument(skip(client))]
+pub async fn deploy(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    labels: BTreeMap<String, String>,
+    image_pull_secrets: Vec<String>,
+    apply_options: &ApplyOptions,
+) -> Result<ServiceAccount, Error> {
+    let image_pull_secrets = (!image_pull_secrets.is_empty()).then(|| {
+        image_pull_secrets
+            .into_iter()
+            .map(|name| LocalObjectReference { name })
+            .collect()
+    });
+
+    let object: ServiceAccount = ServiceAccount {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            namespace: Some(namespace.to_owned()),
+            labels: Some(labels.clone()),
+            ..ObjectMeta::default()
+        },
+        image_pull_secrets,
+        ..ServiceAccount::default()
+    };
+
+    event!(Level::INFO, name, namespace, "Creating ServiceAccount");
+
+    let api: Api<ServiceAccount> = Api::namespaced(client, namespace);
+    let params = apply_options.patch_params();
+    api.patch(name, &params, &Patch::Apply(&object)).await
+}
+
+#[instrument(skip(client))]
+pub async fn delete(
+    client: Client,
+    name: String,
+    namespace: String,
+    propagation_policy: Option<PropagationPolicy>,
+) -> Result<(), Error> {
+    event!(Level::INFO, name, namespace, "Deleting ServiceAccount");
+
+    let api: Api<ServiceAccount> = Api::namespaced(client, namespace.as_str());
+    let dp = DeleteParams {
+        propagation_policy,
+        ..DeleteParams::default()
+    };
+    // If the resource doesn't exist, we can ignore the error
+    ignore_not_found(api.delete(name.as_str(), &dp).await)?;
+    Ok(())
+}
@@ -1,26 +1,398 @@
-use k8s_openapi::api::apps::v1::StatefulSet;
-use kube::api::DeleteParams;
+use k8s_openapi::api::apps::v1::{StatefulSet, StatefulSetSpec};
+use k8s_openapi::api::core::v1::PodSpec;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use kube::api::{DeleteParams, ObjectMeta, Patch, PatchParams, PropagationPolicy};
 use kube::{Api, Client, Error};
+use kube_runtime::wait::{Condition, await_condition};
+use serde_json::json;
+use std::{collections::BTreeMap, time::Duration};
 use tracing::{Level, event, instrument};
 
+use crate::{ApplyOptions, DeleteResult, ignore_not_found};
+
+/// Optional StatefulSet settings beyond the spec and labels. Set fields override
+/// the same fields in the spec passed to `deploy`.
+#[derive(Debug, Default, Clone)]
+pub struct StatefulSetOptions {
+    /// Lets the StatefulSet be garbage-collected along with its owner
+    pub owner: Option<OwnerReference>,
+    /// Seconds a new pod must stay ready before it counts as available,
+    /// which slows a rolling update down to let each peer settle in
+    pub min_ready_seconds: Option<i32>,
+    /// Pod `terminationGracePeriodSeconds`; IPFS nodes need well over the
+    /// 30 second default to flush their datastore on shutdown
+    pub termination_grace_period_seconds: Option<i64>,
+}
+
+#[instrument(skip(client))]
+pub async fn deploy(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    mut spec: StatefulSetSpec,
+    labels: BTreeMap<String, String>,
+    options: StatefulSetOptions,
+    apply_options: &ApplyOptions,
+) -> Result<StatefulSet, Error> {
+    if let Some(seconds) = options.min_ready_seconds {
+        spec.min_ready_seconds = Some(seconds);
+    }
+    if let Some(seconds) = options.termination_grace_period_seconds {
+        spec.template
+            .spec
+            .get_or_insert_with(PodSpec::default)
+            .termination_grace_period_seconds = Some(seconds);
+    }
+
+    let object: StatefulSet = StatefulSet {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            namespace: Some(namespace.to_owned()),
+            labels: Some(labels.clone()),
+            owner_references: options.owner.map(|owner| vec![owner]),
+            ..ObjectMeta::default()
+        },
+        spec: Some(spec),
+        ..StatefulSet::default()
+    };
+
+    event!(Level::INFO, name, namespace, "Creating StatefulSet");
+
+    let api: Api<StatefulSet> = Api::namespaced(client, namespace);
+    let params = apply_options.patch_params();
+    api.patch(name, &params, &Patch::Apply(&object)).await
+}
+
+#[instrument(skip(client))]
+pub async fn get(client: Client, name: &str, namespace: &str) -> Result<StatefulSet, crate::Error> {
+    match get_opt(client, name, namespace).await? {
+        Some(res) => Ok(res),
+        None => Err(crate::Error::StatefulSetError(format!(
+            "StatefulSet {name} not found"
+        ))),
+    }
+}
+
+#[instrument(skip(client))]
+pub async fn get_opt(
+    client: Client,
+    name: &str,
+    namespace: &str,
+) -> Result<Option<StatefulSet>, crate::Error> {
+    let api: Api<StatefulSet> = Api::namespaced(client, namespace);
+    Ok(api.get_opt(name).await?)
+}
+
+/// The commonly checked parts of a StatefulSet's status. Counts are 0 until
+/// the controller first populates the status.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StatefulSetStatusSummary {
+    /// `spec.replicas`, defaulting to 1 like the API server
+    pub desired_replicas: i32,
+    pub replicas: i32,
+    pub ready_replicas: i32,
+    pub current_replicas: i32,
+    pub updated_replicas: i32,
+    pub available_replicas: i32,
+    pub current_revision: Option<String>,
+    pub update_revision: Option<String>,
+    pub observed_generation: Option<i64>,
+}
+
+impl From<&StatefulSet> for StatefulSetStatusSummary {
+    fn from(sts: &StatefulSet) -> Self {
+        let desired_replicas = sts.spec.as_ref().and_then(|s| s.replicas).unwrap_or(1);
+        let Some(status) = &sts.status else {
+            return Self {
+                desired_replicas,
+                ..Self::default()
+            };
+        };
+
+        Self {
+            desired_replicas,
+            replicas: status.replicas,
+            ready_replicas: status.ready_replicas.unwrap_or(0),
+            current_replicas: status.current_replicas.unwrap_or(0),
+            updated_replicas: status.updated_replicas.unwrap_or(0),
+            available_replicas: status.available_replicas.unwrap_or(0),
+            current_revision: status.current_revision.clone(),
+            update_revision: status.update_revision.clone(),
+            observed_generation: status.observed_generation,
+        }
+    }
+}
+
+#[instrument(skip(client))]
+pub async fn get_status(
+    client: Client,
+    name: &str,
+    namespace: &str,
+) -> Result<StatefulSetStatusSummary, crate::Error> {
+    let sts = get(client, name, namespace).await?;
+    Ok(StatefulSetStatusSummary::from(&sts))
+}
+
+/// Patches only `spec.replicas`, leaving fields owned by other managers alone
+#[instrument(skip(client))]
+pub async fn scale(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    replicas: i32,
+) -> Result<StatefulSet, Error> {
+    event!(
+        Level::INFO,
+        name,
+        namespace,
+        replicas,
+        "Scaling StatefulSet"
+    );
+
+    let api: Api<StatefulSet> = Api::namespaced(client, namespace);
+    let patch = json!({ "spec": { "replicas": replicas } });
+    api.patch(name, &PatchParams::default(), &Patch::Merge(&patch))
+        .await
+}
+
+/// Points `container` at `image` with a JSON patch, leaving the rest of the
+/// pod template to whoever manages it. The StatefulSet then rolls its pods as
+/// usual. The patch tests the container name at the found index, so a
+/// concurrent reorder fails the patch instead of changing the wrong container.
+#[instrument(skip(client))]
+pub async fn update_image(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    container: &str,
+    image: &str,
+) -> Result<StatefulSet, crate::Error> {
+    let sts = get(client.clone(), name, namespace).await?;
+    let idx = sts
+        .spec
+        .and_then(|spec| spec.template.spec)
+        .and_then(|spec| spec.containers.iter().position(|c| c.name == container))
+        .ok_or_else(|| crate::Error::ContainerNotFound(container.to_owned()))?;
+
+    event!(
+        Level::INFO,
+        name,
+        namespace,
+        container,
+        image,
+        "Updating StatefulSet image"
+    );
+
+    let path = format!("/spec/template/spec/containers/{idx}");
+    let patch = serde_json::from_value(json!([
+        { "op": "test", "path": format!("{path}/name"), "value": container },
+        { "op": "replace", "path": format!("{path}/image"), "value": image },
+    ]))?;
+
+    let api: Api<StatefulSet> = Api::namespaced(client, namespace);
+    Ok(api
+        .patch(name, &PatchParams::default(), &Patch::Json::<()>(patch))
+        .await?)
+}
+
+/// Waits until every desired replica is both ready and running the current
+/// revision. Timeouts surface as `Error::WaitTimeout`.
 #[instrument(skip(client))]
-pub async fn delete(client: Client, name: String, namespace: String) -> Result<(), Error> {
+pub async fn wait_ready(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    timeout: Duration,
+) -> Result<(), crate::Error> {
+    let api: Api<StatefulSet> = Api::namespaced(client, namespace);
+
+    let ready = await_condition(api, name, is_ready());
+    tokio::time::timeout(timeout, ready).await??;
+    Ok(())
+}
+
+fn is_ready() -> impl Condition<StatefulSet> {
+    move |obj: Option<&StatefulSet>| {
+        if let Some(sts) = &obj
+            && sts.status.is_some()
+            && observed(sts)
+        {
+            let status = StatefulSetStatusSummary::from(*sts);
+            return status.ready_replicas == status.desired_replicas
+                && status.current_replicas == status.desired_replicas;
+        }
+        false
+    }
+}
+
+/// Waits until an updated pod template has reached every replica. Unlike
+/// `wait_ready` this doesn't resolve while pods still run the old revision.
+#[instrument(skip(client))]
+pub async fn wait_rollout(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    timeout: Duration,
+) -> Result<(), crate::Error> {
+    let api: Api<StatefulSet> = Api::namespaced(client, namespace);
+
+    let complete = await_condition(api, name, rollout_complete());
+    tokio::time::timeout(timeout, complete).await??;
+    Ok(())
+}
+
+/// True once the controller has observed the latest generation,
+/// `status.updatedReplicas` matches `spec.replicas` and the current revision
+/// is the update revision. Missing status fields count as incomplete.
+pub fn rollout_complete() -> impl Condition<StatefulSet> {
+    move |obj: Option<&StatefulSet>| {
+        if let Some(sts) = &obj
+            && let Some(status) = &sts.status
+            && observed(sts)
+            && let (Some(updated), Some(current), Some(update)) = (
+                status.updated_replicas,
+                &status.current_revision,
+                &status.update_revision,
+            )
+        {
+            let desired = sts.spec.as_ref().and_then(|s| s.replicas).unwrap_or(1);
+            return updated == desired && current == update;
+        }
+        false
+    }
+}
+
+/// `propagation_policy` of `None` keeps the API server default (background),
+/// which returns before the pods are gone. `Foreground` keeps the StatefulSet
+/// around until its pods are deleted, so combined with `wait_timeout` this
+/// only returns once the pods are gone and PVC or LoadBalancer cleanup
+/// afterwards doesn't race with terminating pods.
+#[instrument(skip(client))]
+pub async fn delete(
+    client: Client,
+    name: String,
+    namespace: String,
+    propagation_policy: Option<PropagationPolicy>,
+    wait_timeout: Option<Duration>,
+) -> Result<DeleteResult, crate::Error> {
     event!(Level::INFO, name, namespace, "Deleting StatefulSet");
 
     let api: Api<StatefulSet> = Api::namespaced(client, namespace.as_str());
-    match api.delete(name.as_str(), &DeleteParams::default()).await {
-        Ok(_) => Ok(()),
-        Err(e) => {
-            match e {
-                // If the resource doesn't exist, we can ignore the error
-                Error::Api(er) => {
-                    if er.reason == "NotFound" {
-                        return Ok(());
-                    };
-                    Err(Error::Api(er))
-                }
-                _ => Err(e),
-            }
+    let dp = DeleteParams {
+        propagation_policy,
+        ..DeleteParams::default()
+    };
+    let result = match ignore_not_found(api.delete(name.as_str(), &dp).await)? {
+        Some(res) => DeleteResult::from(res),
+        None => return Ok(DeleteResult::NotFound),
+    };
+
+    match (result, wait_timeout) {
+        (DeleteResult::Deleting, Some(timeout)) => {
+            crate::wait_for_deletion(api, &name, timeout).await?;
+            Ok(DeleteResult::Deleted)
+        }
+        _ => Ok(result),
+    }
+}
+
+/// Whether the controller has caught up with the latest spec. Until then the
+/// status still describes the previous generation, e.g. right after a
+/// template patch the old revision looks fully rolled out.
+fn observed(sts: &StatefulSet) -> bool {
+    let observed = sts.status.as_ref().and_then(|s| s.observed_generation);
+    observed.unwrap_or(0) >= sts.metadata.generation.unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::apps::v1::StatefulSetStatus;
+
+    use super::*;
+    use crate::testing::mock_client;
+
+    fn rolled_out(generation: i64, observed_generation: i64) -> StatefulSet {
+        StatefulSet {
+            metadata: ObjectMeta {
+                generation: Some(generation),
+                ..ObjectMeta::default()
+            },
+            spec: Some(StatefulSetSpec {
+                replicas: Some(3),
+                ..StatefulSetSpec::default()
+            }),
+            status: Some(StatefulSetStatus {
+                observed_generation: Some(observed_generation),
+                replicas: 3,
+                ready_replicas: Some(3),
+                current_replicas: Some(3),
+                updated_replicas: Some(3),
+                current_revision: Some("sts-1".to_owned()),
+                update_revision: Some("sts-1".to_owned()),
+                ..StatefulSetStatus::default()
+            }),
         }
     }
+
+    #[test]
+    fn rollout_waits_for_the_new_generation_to_be_observed() {
+        let stale = rolled_out(2, 1);
+        assert!(!rollout_complete().matches_object(Some(&stale)));
+        assert!(!is_ready().matches_object(Some(&stale)));
+
+        let current = rolled_out(2, 2);
+        assert!(rollout_complete().matches_object(Some(&current)));
+        assert!(is_ready().matches_object(Some(&current)));
+    }
+
+    #[test]
+    fn rollout_is_incomplete_across_revisions() {
+        let mut sts = rolled_out(2, 2);
+        sts.status.as_mut().unwrap().update_revision = Some("sts-2".to_owned());
+        assert!(!rollout_complete().matches_object(Some(&sts)));
+        assert!(!rollout_complete().matches_object(None));
+    }
+
+    #[tokio::test]
+    async fn scale_patches_only_replicas() {
+        let (client, mut server) = mock_client();
+        let scale = tokio::spawn(async move { scale(client, "ipfs", "ns", 5).await });
+
+        let sts = json!({
+            "apiVersion": "apps/v1",
+            "kind": "StatefulSet",
+            "metadata": { "name": "ipfs", "namespace": "ns" },
+            "spec": { "replicas": 5 },
+        });
+        let body = server
+            .expect_patch("/apis/apps/v1/namespaces/ns/statefulsets/ipfs", sts)
+            .await;
+        assert_eq!(body, json!({ "spec": { "replicas": 5 } }));
+
+        let sts = scale.await.unwrap().unwrap();
+        assert_eq!(sts.spec.unwrap().replicas, Some(5));
+    }
+
+    #[tokio::test]
+    async fn delete_sends_the_propagation_policy() {
+        let (client, mut server) = mock_client();
+        let delete = tokio::spawn(async move {
+            delete(
+                client,
+                "ipfs".to_owned(),
+                "ns".to_owned(),
+                Some(PropagationPolicy::Foreground),
+                None,
+            )
+            .await
+        });
+
+        let status = json!({ "kind": "Status", "apiVersion": "v1", "status": "Success" });
+        let body = server
+            .expect_delete("/apis/apps/v1/namespaces/ns/statefulsets/ipfs", status)
+            .await;
+        assert_eq!(body["propagationPolicy"], "Foreground");
+
+        assert_eq!(delete.await.unwrap().unwrap(), DeleteResult::Deleted);
+    }
 }